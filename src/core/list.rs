@@ -1,167 +1,306 @@
+use rowan::ast::AstNode;
 use std::collections::HashSet;
-use std::str::SplitAsciiWhitespace;
+use std::ops::Range;
 
 use super::option::Option as mxOption;
 use super::transaction::file_lock::NixFile;
-use super::{TABULATION_SIZE, localise_option::SettingsPosition};
+use super::{IndentStyle, localise_option::SettingsPosition};
 use crate::mx;
 
 pub struct List<'a> {
     opt_list: mxOption<'a>,
     unique_value_in_list: bool,
+    coerce_scalar: bool,
 }
 
 impl<'a> List<'a> {
     fn str_is_list(list: &str) -> bool {
-        list.len() >= 2
-            && list.chars().nth(0).unwrap() == '['
-            && list.chars().nth_back(0).unwrap() == ']'
+        list.len() >= 2 && list.starts_with('[') && list.ends_with(']')
+    }
+
+    /// Byte ranges, relative to `list`, of each element of the first list
+    /// parsed in `list`, so that of `with pkgs; [ ... ]` too. A compound
+    /// element such as `(import ./foo.nix)` or a function application is a
+    /// single element.
+    pub(super) fn element_spans(list: &str) -> Vec<Range<usize>> {
+        let Some(ast_list) = rnix::Root::parse(list)
+            .syntax()
+            .descendants()
+            .find_map(rnix::ast::List::cast)
+        else {
+            return Vec::new();
+        };
+        ast_list
+            .items()
+            .map(|item| {
+                let range = item.syntax().text_range();
+                range.start().into()..range.end().into()
+            })
+            .collect()
+    }
+
+    fn elements(list: &str) -> Vec<&str> {
+        Self::element_spans(list)
+            .into_iter()
+            .map(|span| &list[span])
+            .collect()
+    }
+
+    /// Removes the element at `span` along with the whitespace preceding it.
+    fn remove_span(list: &mut String, span: &Range<usize>) {
+        let start = list[..span.start].trim_end_matches([' ', '\t', '\n']).len();
+        list.replace_range(start..span.end, "");
     }
 
     pub fn new(nix_list: &'a str, unique_value: bool) -> Self {
         List {
             opt_list: mxOption::new(nix_list),
             unique_value_in_list: unique_value,
+            coerce_scalar: false,
         }
     }
 
+    /// When adding to an option holding a single value instead of a list,
+    /// turns it into a list of that value, like `imports = ./a.nix;` into
+    /// `imports = [ ./a.nix ./b.nix ];`, instead of failing.
+    #[allow(dead_code)]
+    pub fn with_coerce(mut self, coerce: bool) -> Self {
+        self.coerce_scalar = coerce;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_indent_style(mut self, indent_style: IndentStyle) -> Self {
+        self.opt_list = self.opt_list.with_indent_style(indent_style);
+        self
+    }
+
     pub fn add(&self, nix_file: &mut NixFile, insert_value: &str) -> mx::Result<&Self> {
-        match self.opt_list.get_position(nix_file)? {
+        self.add_in_str(nix_file.get_mut_file_content()?, insert_value)?;
+        Ok(self)
+    }
+
+    /// Same as [`List::add`] on an in-memory content, without any file access.
+    pub fn add_in_str(&self, content: &mut String, insert_value: &str) -> mx::Result<()> {
+        match self.opt_list.get_position_in_str(content)? {
             SettingsPosition::ExistingOption(option) => {
                 let indent_level = option.get_indent_level();
-                let mut list = self.opt_list.get(nix_file)?.to_string();
+                let mut list = self.opt_list.get_in_str(content)?.to_string();
                 if !Self::str_is_list(&list) {
-                    return Err(mx::ErrorKind::OptionIsNotList);
+                    if !self.coerce_scalar {
+                        return Err(mx::ErrorKind::OptionIsNotList);
+                    }
+                    list = format!("[ {} ]", list);
                 }
                 if !self.unique_value_in_list
-                    || list
-                        .strip_prefix('[')
-                        .unwrap()
-                        .strip_suffix(']')
-                        .unwrap()
-                        .split_ascii_whitespace()
-                        .all(|e| e != insert_value)
+                    || Self::elements(&list).iter().all(|e| *e != insert_value)
                 {
-                    let bytes = list.as_bytes();
-                    let mut back = 2;
-                    let newline = loop {
-                        if back > bytes.len() {
-                            break false;
-                        }
-                        let b = bytes[bytes.len() - back];
-                        if b == b'\n' {
-                            break false;
-                        }
-                        if !(b as char).is_whitespace() {
-                            break true;
-                        }
-                        back += 1;
+                    let body = list[..list.len() - 1].trim_end_matches([' ', '\t']);
+                    let is_inline = !list.contains('\n') && !Self::element_spans(&list).is_empty();
+                    list = if is_inline {
+                        let trailing = &list[body.len()..list.len() - 1];
+                        format!("{} {}{}]", body, insert_value, trailing)
+                    } else {
+                        let style = self.opt_list.indent_style_for(content);
+                        let newline = if body.ends_with('\n') { "" } else { "\n" };
+                        format!(
+                            "{}{}{}{}\n{}]",
+                            body,
+                            newline,
+                            style.repeat(indent_level + 1),
+                            insert_value,
+                            style.repeat(indent_level)
+                        )
                     };
-                    back -= TABULATION_SIZE;
-                    let str_before = format!(
-                        "{}{}",
-                        if newline { "\n" } else { "" },
-                        " ".repeat(TABULATION_SIZE * (indent_level as usize + 1) - back)
-                    );
-                    let str_after =
-                        String::from(" ").repeat(TABULATION_SIZE * (indent_level as usize));
-                    list.insert_str(
-                        list.len() - 1usize,
-                        format!("{}{}\n{}", str_before, insert_value, str_after).as_str(),
-                    );
-                    self.opt_list.set(nix_file, &list)?;
+                    self.opt_list.set_in_str(content, &list)?;
                 }
             }
             SettingsPosition::NewInsertion(_) => {
-                self.opt_list.set(nix_file, "[]")?;
-                self.add(nix_file, insert_value)?;
+                self.opt_list.set_in_str(content, "[]")?;
+                self.add_in_str(content, insert_value)?;
             }
         }
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn add_at(
+        &self,
+        nix_file: &mut NixFile,
+        insert_value: &str,
+        index: usize,
+    ) -> mx::Result<&Self> {
+        let SettingsPosition::ExistingOption(_) = self.opt_list.get_position(nix_file)? else {
+            return self.add(nix_file, insert_value);
+        };
+        let mut list = self.opt_list.get(nix_file)?.to_string();
+        if !Self::str_is_list(&list) {
+            return Err(mx::ErrorKind::OptionIsNotList);
+        }
+
+        let spans = Self::element_spans(&list);
+        if self.unique_value_in_list && spans.iter().any(|span| list[span.clone()] == *insert_value)
+        {
+            return Ok(self);
+        }
+        let Some(span) = spans.get(index) else {
+            return self.add(nix_file, insert_value);
+        };
+
+        let previous_end = if index == 0 { 1 } else { spans[index - 1].end };
+        let separator = match &list[previous_end..span.start] {
+            "" => " ".to_string(),
+            separator => separator.to_string(),
+        };
+        list.insert_str(span.start, &format!("{}{}", insert_value, separator));
+        self.opt_list.set(nix_file, &list)?;
         Ok(self)
     }
 
     pub fn remove(&self, nix_file: &mut NixFile, value: &str) -> mx::Result<&Self> {
+        self.remove_in_str(nix_file.get_mut_file_content()?, value)?;
+        Ok(self)
+    }
+
+    /// Same as [`List::remove`] on an in-memory content, without any file access.
+    pub fn remove_in_str(&self, content: &mut String, value: &str) -> mx::Result<()> {
+        self.remove_first_matching_in_str(content, |element| element == value)?;
+        Ok(())
+    }
+
+    /// Removes the first element whose source text satisfies `matches`, to
+    /// find an element by one of its fields rather than by its whole text.
+    /// Returns `true` if an element was removed.
+    pub fn remove_first_matching_in_str(
+        &self,
+        content: &mut String,
+        matches: impl Fn(&str) -> bool,
+    ) -> mx::Result<bool> {
+        match self.opt_list.get_position_in_str(content)? {
+            SettingsPosition::ExistingOption(_) => {
+                let mut list = self.opt_list.get_in_str(content)?.to_string();
+                if !Self::str_is_list(&list) {
+                    return Err(mx::ErrorKind::OptionIsNotList);
+                }
+
+                let spans = Self::element_spans(&list);
+                let Some(span) = spans.iter().find(|span| matches(&list[(*span).clone()])) else {
+                    return Ok(false);
+                };
+                if spans.len() == 1 {
+                    self.opt_list.set_option_to_default_in_str(content)?;
+                } else {
+                    Self::remove_span(&mut list, span);
+                    self.opt_list.set_in_str(content, &list)?;
+                }
+                Ok(true)
+            }
+            SettingsPosition::NewInsertion(_) => Ok(false),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_all(&self, nix_file: &mut NixFile, value: &str) -> mx::Result<usize> {
         match self.opt_list.get_position(nix_file)? {
             SettingsPosition::ExistingOption(_) => {
                 let mut list = self.opt_list.get(nix_file)?.to_string();
-
-                let mut start: usize = 0;
-                let mut end: usize = 0;
-                let mut found = false;
-                let mut _offset = 1;
-
-                for elem in self.get_element_in_list(nix_file)? {
-                    let s = list[_offset..].find(elem).unwrap() + _offset;
-                    let e = s + elem.len();
-                    if elem == value {
-                        start = s;
-                        end = e;
-                        _offset = end;
-                        found = true;
-                        break;
-                    }
+                if !Self::str_is_list(&list) {
+                    return Err(mx::ErrorKind::OptionIsNotList);
                 }
 
-                if found {
-                    if list
-                        .strip_prefix('[')
-                        .unwrap()
-                        .strip_suffix(']')
-                        .unwrap()
-                        .split_ascii_whitespace()
-                        .count()
-                        == 1
-                    {
-                        self.opt_list.set_option_to_default(nix_file)?;
-                    } else {
-                        list.replace_range(start..end, "");
-                        let mut pos = start - 1;
-                        while pos > 0
-                            && match list.chars().nth(pos) {
-                                Some(' ') | Some('\t') | Some('\n') => true,
-                                Some(_) | _ => false,
-                            }
-                        {
-                            list.remove(pos);
-                            pos -= 1;
-                        }
-                        self.opt_list.set(nix_file, &list)?;
+                let spans = Self::element_spans(&list);
+                let matching: Vec<Range<usize>> = spans
+                    .iter()
+                    .filter(|span| list[(*span).clone()] == *value)
+                    .cloned()
+                    .collect();
+
+                if matching.is_empty() {
+                    return Ok(0);
+                }
+                if matching.len() == spans.len() {
+                    self.opt_list.set_option_to_default(nix_file)?;
+                } else {
+                    for span in matching.iter().rev() {
+                        Self::remove_span(&mut list, span);
                     }
+                    self.opt_list.set(nix_file, &list)?;
                 }
+                Ok(matching.len())
             }
-            SettingsPosition::NewInsertion(_) => (),
+            SettingsPosition::NewInsertion(_) => Ok(0),
         }
-        Ok(self)
+    }
+
+    #[allow(dead_code)]
+    pub fn dedup(&self, nix_file: &mut NixFile) -> mx::Result<usize> {
+        let mut list = match self.opt_list.get(nix_file) {
+            Ok(list) if Self::str_is_list(list) => list.to_string(),
+            Ok(_) | Err(mx::ErrorKind::OptionNotFound) => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let mut seen = HashSet::new();
+        let duplicates: Vec<Range<usize>> = Self::element_spans(&list)
+            .into_iter()
+            .filter(|span| !seen.insert(list[span.clone()].to_string()))
+            .collect();
+
+        for span in duplicates.iter().rev() {
+            Self::remove_span(&mut list, span);
+        }
+        if !duplicates.is_empty() {
+            self.opt_list.set(nix_file, &list)?;
+        }
+        Ok(duplicates.len())
+    }
+
+    /// Returns each element of the list with its byte range in the file.
+    /// Elements are the items of the parsed list, so a compound element like
+    /// `(pkgs.callPackage ./x.nix { })` is returned as a single one.
+    #[allow(dead_code)]
+    pub fn get_elements_with_ranges(
+        &self,
+        nix_file: &NixFile,
+    ) -> mx::Result<Vec<(String, Range<usize>)>> {
+        let SettingsPosition::ExistingOption(option) = self.opt_list.get_position(nix_file)? else {
+            return Err(mx::ErrorKind::OptionNotFound);
+        };
+        let offset = option.get_range_option_value().start;
+        let list = &nix_file.get_file_content()?[option.get_range_option_value().clone()];
+        if !Self::str_is_list(list) {
+            return Err(mx::ErrorKind::OptionIsNotList);
+        }
+        Ok(Self::element_spans(list)
+            .into_iter()
+            .map(|span| {
+                (
+                    list[span.clone()].to_string(),
+                    span.start + offset..span.end + offset,
+                )
+            })
+            .collect())
     }
 
     pub fn get_element_in_list(
         &self,
         nix_file: &'a NixFile,
-    ) -> mx::Result<SplitAsciiWhitespace<'a>> {
+    ) -> mx::Result<impl Iterator<Item = &'a str>> {
         let list = self.opt_list.get(nix_file)?;
         if !Self::str_is_list(&list) {
             return Err(mx::ErrorKind::OptionIsNotList);
         }
-        Ok(list
-            .strip_prefix('[')
-            .unwrap()
-            .strip_suffix(']')
-            .unwrap()
-            .split_ascii_whitespace())
+        Ok(Self::elements(list).into_iter())
     }
 
     #[allow(dead_code)]
     pub fn eq(&self, nix_file: &NixFile, desired_value: &[&str]) -> mx::Result<bool> {
         //let opt = get_option(file_content, list_name)?;
-        let set_current_list: HashSet<&str> = self
-            .opt_list
-            .get(nix_file)?
-            .strip_prefix('[')
-            .ok_or(mx::ErrorKind::OptionIsNotList)?
-            .strip_suffix(']')
-            .ok_or(mx::ErrorKind::OptionIsNotList)?
-            .split_ascii_whitespace()
-            .collect();
+        let list = self.opt_list.get(nix_file)?;
+        if !Self::str_is_list(list) {
+            return Err(mx::ErrorKind::OptionIsNotList);
+        }
+        let set_current_list: HashSet<&str> = Self::elements(list).into_iter().collect();
 
         let set_desired_value: HashSet<&str> = desired_value.iter().copied().collect();
 
@@ -169,17 +308,16 @@ impl<'a> List<'a> {
     }
 
     #[allow(dead_code)]
-    pub fn countains(&self, nix_file: &NixFile, desired_value: &str) -> mx::Result<bool> {
+    pub fn contains(&self, nix_file: &NixFile, desired_value: &str) -> mx::Result<bool> {
         Ok(match self.opt_list.get(nix_file) {
-            Ok(list) => list
-                .strip_prefix('[')
-                .ok_or(mx::ErrorKind::OptionIsNotList)?
-                .strip_suffix(']')
-                .ok_or(mx::ErrorKind::OptionIsNotList)?
-                .split_ascii_whitespace()
-                .any(|v| v == desired_value),
+            Ok(list) if Self::str_is_list(list) => Self::elements(list).contains(&desired_value),
+            Ok(_) => return Err(mx::ErrorKind::OptionIsNotList),
             Err(mx::ErrorKind::OptionNotFound) => false,
             Err(e) => return Err(e),
         })
     }
 }
+
+#[cfg(test)]
+#[path = "list_tests.rs"]
+mod tests;
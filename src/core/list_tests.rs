@@ -0,0 +1,490 @@
+/// Tests for [`List`].
+///
+/// # Structure
+/// - `unit`        – List edits applied to in-memory content, with no file access.
+/// - `integration` – List edits applied to a `configuration.nix` opened
+///   through a real [`Transaction`] on a temporary Git repository.
+use super::List;
+use crate::core::transaction::file_lock::NixFile;
+use crate::core::transaction::{Transaction, transaction::BuildCommand};
+use crate::mx;
+use std::fs;
+use tempfile::TempDir;
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Helpers
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Stages and commits every file currently in the working tree.
+fn commit_all(repo: &git2::Repository) {
+    let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+}
+
+/// Opens `content` as `configuration.nix` inside a transaction, applies `f`
+/// and returns its result along with the resulting in-memory content. The
+/// transaction is rolled back so nothing is written to disk.
+fn edit<F, R>(content: &str, f: F) -> mx::Result<(R, String)>
+where
+    F: FnOnce(&mut NixFile) -> mx::Result<R>,
+{
+    let dir = TempDir::new().expect("failed to create temporary directory");
+    let repo = git2::Repository::init(dir.path()).expect("git init failed");
+    fs::write(dir.path().join("configuration.nix"), content)
+        .expect("failed to write configuration.nix");
+    commit_all(&repo);
+
+    let repo_path = format!("{}/", dir.path().to_str().unwrap());
+    let mut transaction = Transaction::new(&repo_path, "test", BuildCommand::Install)?;
+    transaction.begin()?;
+    let file = transaction.get_file("configuration.nix")?;
+    let result = f(file).and_then(|ret| Ok((ret, file.get_file_content()?.clone())));
+    transaction.rollback()?;
+    result
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Unit tests – in-memory content only
+// ─────────────────────────────────────────────────────────────────────────────
+mod unit {
+    use super::*;
+
+    /// Multibyte elements are added and removed without splitting a character.
+    #[test]
+    fn multibyte_elements_in_str() {
+        let mut content = String::from("{\n  # é\n  l = [ \"é\" \"ö\" ];\n}\n");
+        let list = List::new("l", true);
+        list.add_in_str(&mut content, "\"☕\"").unwrap();
+        list.remove_in_str(&mut content, "\"é\"").unwrap();
+        assert_eq!(content, "{\n  # é\n  l = [ \"ö\" \"☕\" ];\n}\n");
+    }
+
+    /// A `with pkgs;` prefix is kept when the list is edited.
+    #[test]
+    fn add_and_remove_in_with_list() {
+        let mut content =
+            String::from("{\n  environment.systemPackages = with pkgs; [ vim git ];\n}\n");
+        let list = List::new("environment.systemPackages", true);
+        list.add_in_str(&mut content, "htop").unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = with pkgs; [ vim git htop ];\n}\n"
+        );
+        list.remove_in_str(&mut content, "vim").unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = with pkgs; [ git htop ];\n}\n"
+        );
+
+        let mut content =
+            String::from("{\n  environment.systemPackages = with pkgs; [\n    vim\n  ];\n}\n");
+        list.add_in_str(&mut content, "git").unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = with pkgs; [\n    vim\n    git\n  ];\n}\n"
+        );
+    }
+
+    /// `add_in_str` and `remove_in_str` edit a plain string.
+    #[test]
+    fn add_and_remove_in_str() {
+        let mut content = String::from("{\n}\n");
+        let list = List::new("environment.systemPackages", true);
+        list.add_in_str(&mut content, "pkgs.vim").unwrap();
+        list.add_in_str(&mut content, "pkgs.git").unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment = {\n    systemPackages = [\n      pkgs.vim\n      pkgs.git\n    ];\n  };\n}\n"
+        );
+        list.remove_in_str(&mut content, "pkgs.vim").unwrap();
+        list.remove_in_str(&mut content, "pkgs.git").unwrap();
+        assert_eq!(content, "{\n  environment = {\n  };\n}\n");
+    }
+
+    /// With `with_coerce`, a single value becomes the first element of the
+    /// list the new value is added to.
+    #[test]
+    fn add_coerces_scalar_into_list() {
+        let mut content = String::from("{\n  imports = ./single.nix;\n}\n");
+        List::new("imports", true)
+            .with_coerce(true)
+            .add_in_str(&mut content, "./other.nix")
+            .unwrap();
+        assert_eq!(content, "{\n  imports = [ ./single.nix ./other.nix ];\n}\n");
+    }
+
+    /// Values that are not a bracketed list, a lone `[` or `]` included, are
+    /// refused with `OptionIsNotList` and the content is left untouched.
+    #[test]
+    fn edit_in_str_on_non_list_errors() {
+        for value in ["1", "\"[\"", "./single.nix", "lib.mkForce [ x ]"] {
+            let original = format!("{{\n  a = {};\n}}\n", value);
+            let mut content = original.clone();
+            let list = List::new("a", true);
+            assert!(matches!(
+                list.add_in_str(&mut content, "x"),
+                Err(mx::ErrorKind::OptionIsNotList)
+            ));
+            assert!(matches!(
+                list.remove_in_str(&mut content, "x"),
+                Err(mx::ErrorKind::OptionIsNotList)
+            ));
+            assert_eq!(content, original);
+        }
+    }
+
+    /// Only the first element accepted by the predicate is removed.
+    #[test]
+    fn remove_first_matching_in_str() {
+        let mut content = String::from("{\n  l = [ { a = 1; } { a = 2; } { a = 2; } ];\n}\n");
+        let list = List::new("l", false);
+        let removed = list
+            .remove_first_matching_in_str(&mut content, |e| e.contains("a = 2;"))
+            .unwrap();
+        assert!(removed);
+        assert_eq!(content, "{\n  l = [ { a = 1; } { a = 2; } ];\n}\n");
+        let removed = list
+            .remove_first_matching_in_str(&mut content, |e| e.contains("a = 3;"))
+            .unwrap();
+        assert!(!removed);
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Integration tests
+// ─────────────────────────────────────────────────────────────────────────────
+mod integration {
+    use super::*;
+
+    const PACKAGES: &str =
+        "{\n  environment.systemPackages = [\n    pkgs.vim\n    pkgs.git\n  ];\n}\n";
+
+    // ── contains ──────────────────────────────────────────────────────────────
+
+    /// A value present in the list is found.
+    #[test]
+    fn contains_present_value() {
+        let (found, _) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).contains(file, "pkgs.git")
+        })
+        .unwrap();
+        assert!(found);
+    }
+
+    /// A value absent from the list, even as a prefix of an element, is not found.
+    #[test]
+    fn contains_absent_value() {
+        let (found, _) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).contains(file, "pkgs.gi")
+        })
+        .unwrap();
+        assert!(!found);
+    }
+
+    /// A missing list option is reported as `false`, not as an error.
+    #[test]
+    fn contains_missing_option() {
+        let (found, _) = edit(PACKAGES, |file| {
+            List::new("fonts.packages", true).contains(file, "pkgs.git")
+        })
+        .unwrap();
+        assert!(!found);
+    }
+
+    /// A scalar option is not a list.
+    #[test]
+    fn contains_on_scalar_is_error() {
+        let result = edit("{\n  a = 1;\n}\n", |file| {
+            List::new("a", true).contains(file, "1")
+        });
+        assert!(matches!(result, Err(mx::ErrorKind::OptionIsNotList)));
+    }
+
+    // ── elements with ranges ──────────────────────────────────────────────────
+
+    /// A compound element is one element, and each range maps back to its text.
+    #[test]
+    fn elements_with_ranges_compound_element() {
+        let content =
+            "{\n  environment.systemPackages = [ pkgs.vim (pkgs.callPackage ./x.nix { }) ];\n}\n";
+        let (elements, _) = edit(content, |file| {
+            List::new("environment.systemPackages", true).get_elements_with_ranges(file)
+        })
+        .unwrap();
+        let texts: Vec<&str> = elements.iter().map(|(text, _)| text.as_str()).collect();
+        assert_eq!(texts, ["pkgs.vim", "(pkgs.callPackage ./x.nix { })"]);
+        for (text, range) in &elements {
+            assert_eq!(&content[range.clone()], text);
+        }
+    }
+
+    /// Parenthesized expressions and multi-argument applications are single
+    /// elements for every list operation.
+    #[test]
+    fn compound_elements_are_single_elements() {
+        let content = "{\n  environment.systemPackages = [\n    (import ./foo.nix)\n    (pkgs.writeText \"name\" \"body\")\n    pkgs.vim\n  ];\n}\n";
+        let ((count, found, absent), content) = edit(content, |file| {
+            let list = List::new("environment.systemPackages", true);
+            let count = list.get_element_in_list(file)?.count();
+            let found = list.contains(file, "(import ./foo.nix)")?;
+            let absent = list.contains(file, "./foo.nix)")?;
+            list.add(file, "(import ./foo.nix)")?;
+            list.remove(file, "(pkgs.writeText \"name\" \"body\")")?;
+            Ok((count, found, absent))
+        })
+        .unwrap();
+        assert_eq!(count, 3);
+        assert!(found);
+        assert!(!absent);
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    (import ./foo.nix)\n    pkgs.vim\n  ];\n}\n"
+        );
+    }
+
+    // ── add / add_at ──────────────────────────────────────────────────────────
+
+    /// Appending keeps one element per line at the list indentation.
+    #[test]
+    fn add_appends_on_new_line() {
+        let (_, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).add(file, "pkgs.htop")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    pkgs.vim\n    pkgs.git\n    pkgs.htop\n  ];\n}\n"
+        );
+    }
+
+    /// A list written on one line stays on one line.
+    #[test]
+    fn add_keeps_inline_list_inline() {
+        let (_, content) = edit("{\n  a = [ pkgs.vim pkgs.git ];\n}\n", |file| {
+            List::new("a", true).add(file, "pkgs.htop")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = [ pkgs.vim pkgs.git pkgs.htop ];\n}\n");
+
+        let (_, content) = edit("{\n  a = [pkgs.vim];\n}\n", |file| {
+            List::new("a", true).add(file, "pkgs.htop")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = [pkgs.vim pkgs.htop];\n}\n");
+    }
+
+    /// An empty or missing list is laid out vertically.
+    #[test]
+    fn add_to_empty_list_is_multiline() {
+        let (_, content) = edit("{\n  a = [];\n}\n", |file| {
+            List::new("a", true).add(file, "x")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = [\n    x\n  ];\n}\n");
+    }
+
+    /// Adding to or removing from a scalar option reports `OptionIsNotList`.
+    #[test]
+    fn edit_scalar_is_option_is_not_list() {
+        let result = edit("{\n  a = 1;\n}\n", |file| {
+            List::new("a", true).add(file, "x")?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(mx::ErrorKind::OptionIsNotList)));
+
+        let result = edit("{\n  a = 1;\n}\n", |file| {
+            List::new("a", true).remove(file, "1")?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(mx::ErrorKind::OptionIsNotList)));
+    }
+
+    /// Inserting at index 0 places the value first, on its own line.
+    #[test]
+    fn add_at_start() {
+        let (_, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).add_at(file, "pkgs.htop", 0)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    pkgs.htop\n    pkgs.vim\n    pkgs.git\n  ];\n}\n"
+        );
+    }
+
+    /// Inserting in the middle reuses the separator of the displaced element.
+    #[test]
+    fn add_at_middle() {
+        let (_, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).add_at(file, "pkgs.htop", 1)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    pkgs.vim\n    pkgs.htop\n    pkgs.git\n  ];\n}\n"
+        );
+
+        let (_, content) = edit("{\n  a = [ x y ];\n}\n", |file| {
+            List::new("a", true).add_at(file, "z", 1)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = [ x z y ];\n}\n");
+    }
+
+    /// An index past the end appends.
+    #[test]
+    fn add_at_past_end_appends() {
+        let (_, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).add_at(file, "pkgs.htop", 42)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    pkgs.vim\n    pkgs.git\n    pkgs.htop\n  ];\n}\n"
+        );
+    }
+
+    /// A unique list is left untouched when the value is already present.
+    #[test]
+    fn add_at_unique_already_present() {
+        let (_, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).add_at(file, "pkgs.git", 0)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, PACKAGES);
+    }
+
+    // ── remove ────────────────────────────────────────────────────────────────
+
+    /// Only the exact element is removed, never the prefix of a longer one.
+    #[test]
+    fn remove_exact_token_only() {
+        let content = "{\n  environment.systemPackages = [\n    pkgs.firefox-esr\n    pkgs.firefox\n  ];\n}\n";
+        let (_, content) = edit(content, |file| {
+            List::new("environment.systemPackages", true).remove(file, "pkgs.firefox")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    pkgs.firefox-esr\n  ];\n}\n"
+        );
+    }
+
+    /// Removing the first element keeps the following ones indented.
+    #[test]
+    fn remove_first_element() {
+        let (_, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", true).remove(file, "pkgs.vim")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    pkgs.git\n  ];\n}\n"
+        );
+    }
+
+    /// Removing the last remaining element removes the option altogether.
+    #[test]
+    fn remove_last_element_removes_option() {
+        let content = "{\n  a = 1;\n  b = [ x ];\n}\n";
+        let (_, content) = edit(content, |file| {
+            List::new("b", true).remove(file, "x")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+    }
+
+    // ── remove_all ────────────────────────────────────────────────────────────
+
+    /// Every copy of the value is removed and counted, other elements are kept.
+    #[test]
+    fn remove_all_interleaved_copies() {
+        let content = "{\n  environment.systemPackages = [\n    pkgs.vim\n    pkgs.git\n    pkgs.vim\n    pkgs.htop\n    pkgs.vim\n  ];\n}\n";
+        let (removed, content) = edit(content, |file| {
+            List::new("environment.systemPackages", false).remove_all(file, "pkgs.vim")
+        })
+        .unwrap();
+        assert_eq!(removed, 3);
+        assert_eq!(
+            content,
+            "{\n  environment.systemPackages = [\n    pkgs.git\n    pkgs.htop\n  ];\n}\n"
+        );
+    }
+
+    /// Removing every element removes the option altogether.
+    #[test]
+    fn remove_all_emptying_list_removes_option() {
+        let content = "{\n  a = 1;\n  b = [ x x ];\n}\n";
+        let (removed, content) =
+            edit(content, |file| List::new("b", false).remove_all(file, "x")).unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+    }
+
+    /// Nothing is removed when the value or the option is absent.
+    #[test]
+    fn remove_all_absent() {
+        let (removed, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", false).remove_all(file, "pkgs.htop")
+        })
+        .unwrap();
+        assert_eq!(removed, 0);
+        assert_eq!(content, PACKAGES);
+
+        let (removed, _) = edit(PACKAGES, |file| {
+            List::new("fonts.packages", false).remove_all(file, "x")
+        })
+        .unwrap();
+        assert_eq!(removed, 0);
+    }
+
+    // ── dedup ─────────────────────────────────────────────────────────────────
+
+    /// Later duplicates are dropped, first-seen order is preserved.
+    #[test]
+    fn dedup_keeps_first_occurrences() {
+        let (removed, content) = edit("{\n  l = [ a b a c b ];\n}\n", |file| {
+            List::new("l", false).dedup(file)
+        })
+        .unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(content, "{\n  l = [ a b c ];\n}\n");
+    }
+
+    /// A list without duplicates, a scalar or a missing option are left untouched.
+    #[test]
+    fn dedup_noop() {
+        let (removed, content) = edit(PACKAGES, |file| {
+            List::new("environment.systemPackages", false).dedup(file)
+        })
+        .unwrap();
+        assert_eq!(removed, 0);
+        assert_eq!(content, PACKAGES);
+
+        let (removed, _) =
+            edit("{\n  a = 1;\n}\n", |file| List::new("a", false).dedup(file)).unwrap();
+        assert_eq!(removed, 0);
+
+        let (removed, _) = edit(PACKAGES, |file| List::new("b", false).dedup(file)).unwrap();
+        assert_eq!(removed, 0);
+    }
+}
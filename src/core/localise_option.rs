@@ -1,14 +1,84 @@
 use rnix::TextRange;
-use rnix::ast::{AttrSet, AttrpathValue, Expr, HasEntry};
+use rnix::ast::{AttrSet, AttrpathValue, Expr, HasEntry, Inherit, LiteralKind};
 use rowan::ast::AstNode;
+use std::collections::HashSet;
+use std::fs;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
 
+use crate::core::list::List;
 use crate::mx;
 
 fn text_range_to_range(r: TextRange) -> Range<usize> {
     r.start().into()..r.end().into()
 }
 
+/// Attribute set guarded by a `lib.mkIf cond { ... }` (or `mkIf cond { ... }`)
+/// application, `None` for any other expression.
+fn conditional_body(expr: &Expr) -> Option<AttrSet> {
+    let Expr::Apply(apply) = expr else {
+        return None;
+    };
+    let Expr::Apply(head) = apply.lambda()? else {
+        return None;
+    };
+    let is_mk_if = match head.lambda()? {
+        Expr::Ident(ident) => ident.to_string() == "mkIf",
+        Expr::Select(select) => select
+            .attrpath()
+            .and_then(|path| path.attrs().last())
+            .is_some_and(|attr| attr.to_string() == "mkIf"),
+        _ => false,
+    };
+    if !is_mk_if {
+        return None;
+    }
+    match apply.argument()? {
+        Expr::AttrSet(set) => Some(set),
+        Expr::Paren(paren) => match paren.expr()? {
+            Expr::AttrSet(set) => Some(set),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Splits an option path on the dots that are outside of quoted segments.
+///
+/// This is the canonical path syntax: a key that is a string literal in the
+/// file and contains a dot must be quoted, like
+/// `services.nginx.virtualHosts."example.com".root`. Quotes are optional for
+/// any other key, `"foo"` and `foo` naming the same attribute.
+pub fn split_option_path(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in path.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '.' if !in_quotes => {
+                segments.push(&path[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    segments.push(&path[start..]);
+    segments
+}
+
+/// Strips the quotes around a path segment or a string-literal key, so that
+/// `"foo"` and `foo` compare equal.
+pub fn normalize_segment(segment: &str) -> &str {
+    segment
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(segment)
+}
+
 #[derive(Debug, Clone)]
 pub struct NewInsertion {
     pos: usize,
@@ -16,11 +86,49 @@ pub struct NewInsertion {
     indent_level: usize,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Bool,
+    Int,
+    Float,
+    String,
+    Path,
+    List,
+    AttrSet,
+    Ident,
+    /// Defined by an `inherit` statement, without an inline value.
+    Inherit,
+    Other,
+}
+
 #[derive(Debug, Clone)]
 pub struct ExistingOption {
     range_path: Range<usize>,
+    range_key: Range<usize>,
     range_value: Range<usize>,
     indent_level: usize,
+    value_kind: ValueKind,
+}
+
+/// 1-based position in a source, the column being counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Owned copy of a [`SettingsPosition`] with fixed-size integers, for bindings.
+/// A new insertion has an empty definition range at the insertion point and
+/// `-1` as value bounds.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PositionInfo {
+    pub def_start: u32,
+    pub def_end: u32,
+    pub value_start: i64,
+    pub value_end: i64,
+    /// Part of the path left to insert, `None` for an existing option.
+    pub remaining_path: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -51,12 +159,73 @@ impl NewInsertion {
     }
 }
 
+impl LineCol {
+    fn from_offset(source: &str, offset: usize) -> Self {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        LineCol {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl ValueKind {
+    /// Kind of the Nix expression `value`, [`ValueKind::Other`] if it does not
+    /// parse.
+    pub fn of_source(value: &str) -> Self {
+        let root = rnix::Root::parse(value);
+        match root.tree().expr() {
+            Some(expr) if root.errors().is_empty() => Self::from_expr(&expr),
+            _ => ValueKind::Other,
+        }
+    }
+
+    /// `false` for the kinds whose type is only known once evaluated, like an
+    /// identifier or a function call.
+    pub fn is_literal(self) -> bool {
+        !matches!(
+            self,
+            ValueKind::Ident | ValueKind::Inherit | ValueKind::Other
+        )
+    }
+
+    fn from_expr(value: &Expr) -> Self {
+        match value {
+            Expr::Ident(ident) => match ident.to_string().as_str() {
+                "true" | "false" => ValueKind::Bool,
+                _ => ValueKind::Ident,
+            },
+            Expr::Literal(literal) => match literal.kind() {
+                LiteralKind::Integer(_) => ValueKind::Int,
+                LiteralKind::Float(_) => ValueKind::Float,
+                LiteralKind::Uri(_) => ValueKind::String,
+            },
+            Expr::Str(_) => ValueKind::String,
+            Expr::PathAbs(_) | Expr::PathRel(_) | Expr::PathHome(_) | Expr::PathSearch(_) => {
+                ValueKind::Path
+            }
+            Expr::List(_) => ValueKind::List,
+            Expr::AttrSet(_) => ValueKind::AttrSet,
+            _ => ValueKind::Other,
+        }
+    }
+}
+
 impl ExistingOption {
-    pub fn new(range_path: Range<usize>, range_value: Range<usize>, indent_level: usize) -> Self {
+    pub fn new(
+        range_path: Range<usize>,
+        range_key: Range<usize>,
+        range_value: Range<usize>,
+        indent_level: usize,
+        value_kind: ValueKind,
+    ) -> Self {
         ExistingOption {
             range_path,
+            range_key,
             range_value,
             indent_level,
+            value_kind,
         }
     }
 
@@ -64,6 +233,12 @@ impl ExistingOption {
         &self.range_path
     }
 
+    /// Range of the attribute path alone, `key` in `key = value;`.
+    #[allow(dead_code)]
+    pub fn get_range_key(&self) -> &Range<usize> {
+        &self.range_key
+    }
+
     pub fn get_range_option_value(&self) -> &Range<usize> {
         &self.range_value
     }
@@ -71,6 +246,18 @@ impl ExistingOption {
     pub fn get_indent_level(&self) -> usize {
         self.indent_level
     }
+
+    pub fn get_value_kind(&self) -> ValueKind {
+        self.value_kind
+    }
+
+    #[allow(dead_code)]
+    pub fn get_range_option_line_col(&self, source: &str) -> (LineCol, LineCol) {
+        (
+            LineCol::from_offset(source, self.range_path.start),
+            LineCol::from_offset(source, self.range_path.end),
+        )
+    }
 }
 
 impl SettingsPosition {
@@ -78,6 +265,54 @@ impl SettingsPosition {
         Self::localise_option(nix_ast, settings, 0).ok_or(mx::ErrorKind::InvalidFile)
     }
 
+    /// Owned copy of the position, see [`PositionInfo`].
+    #[allow(dead_code)]
+    pub fn to_info(&self) -> PositionInfo {
+        match self {
+            SettingsPosition::ExistingOption(option) => PositionInfo {
+                def_start: option.get_range_option().start as u32,
+                def_end: option.get_range_option().end as u32,
+                value_start: option.get_range_option_value().start as i64,
+                value_end: option.get_range_option_value().end as i64,
+                remaining_path: None,
+            },
+            SettingsPosition::NewInsertion(insertion) => PositionInfo {
+                def_start: insertion.get_pos_new_insertion() as u32,
+                def_end: insertion.get_pos_new_insertion() as u32,
+                value_start: -1,
+                value_end: -1,
+                remaining_path: Some(insertion.get_remaining_path().to_string()),
+            },
+        }
+    }
+
+    /// Range of the key of an existing option, `None` for a new insertion.
+    #[allow(dead_code)]
+    pub fn get_range_key(&self) -> Option<&Range<usize>> {
+        match self {
+            SettingsPosition::ExistingOption(option) => Some(option.get_range_key()),
+            SettingsPosition::NewInsertion(_) => None,
+        }
+    }
+
+    /// Nesting depth of the attribute set holding the option or the insertion
+    /// point. The top-level set is at level 1, each nested set adds one.
+    #[allow(dead_code)]
+    pub fn get_indent_level(&self) -> usize {
+        match self {
+            SettingsPosition::ExistingOption(option) => option.get_indent_level(),
+            SettingsPosition::NewInsertion(insertion) => insertion.get_indent_level(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_value_kind(&self) -> Option<ValueKind> {
+        match self {
+            SettingsPosition::ExistingOption(option) => Some(option.get_value_kind()),
+            SettingsPosition::NewInsertion(_) => None,
+        }
+    }
+
     fn localise_option(
         node: &rnix::SyntaxNode,
         settings: &str,
@@ -91,6 +326,10 @@ impl SettingsPosition {
             ));
         }
 
+        if let Some(set) = Expr::cast(node.clone()).and_then(|expr| conditional_body(&expr)) {
+            return Some(Self::localise_in_attr_set(&set, settings, indent_level + 1));
+        }
+
         if let Some(apv) = AttrpathValue::cast(node.clone()) {
             return Self::localise_in_attrpath_value(&apv, settings, indent_level);
         }
@@ -112,8 +351,14 @@ impl SettingsPosition {
         let mut best: Option<NewInsertion> = None;
 
         for entry in attr_set.entries() {
-            let rnix::ast::Entry::AttrpathValue(apv) = entry else {
-                continue;
+            let apv = match entry {
+                rnix::ast::Entry::AttrpathValue(apv) => apv,
+                rnix::ast::Entry::Inherit(inherit) => {
+                    if let Some(pos) = Self::inherited_option(&inherit, settings, indent_level) {
+                        return pos;
+                    }
+                    continue;
+                }
             };
 
             let Some(pos) = Self::localise_in_attrpath_value(&apv, settings, indent_level) else {
@@ -147,69 +392,347 @@ impl SettingsPosition {
         settings: &str,
         indent_level: usize,
     ) -> Option<SettingsPosition> {
+        let remaining = Self::match_attrpath(apv, settings)?;
+
+        match apv.value()? {
+            Expr::AttrSet(set) => {
+                if remaining.is_empty() {
+                    return Some(Self::existing_option(
+                        apv,
+                        &Expr::AttrSet(set),
+                        indent_level,
+                    ));
+                }
+
+                Some(Self::localise_in_attr_set(
+                    &set,
+                    &remaining,
+                    indent_level + 1,
+                ))
+            }
+            other => match conditional_body(&other) {
+                Some(set) if !remaining.is_empty() => Some(Self::localise_in_attr_set(
+                    &set,
+                    &remaining,
+                    indent_level + 1,
+                )),
+                _ => Self::value_position(apv, other, indent_level),
+            },
+        }
+    }
+
+    fn match_attrpath(apv: &AttrpathValue, settings: &str) -> Option<String> {
         let attrpath = apv.attrpath()?;
 
         let attr_segments: Vec<String> = attrpath.attrs().map(|a| a.to_string()).collect();
 
-        let settings_segments: Vec<&str> = settings.split('.').collect();
+        let settings_segments = split_option_path(settings);
 
         let is_prefix = attr_segments.len() <= settings_segments.len()
             && attr_segments
                 .iter()
                 .zip(settings_segments.iter())
-                .all(|(a, s)| a == s);
+                .all(|(a, s)| normalize_segment(a) == normalize_segment(s));
 
         if !is_prefix {
             return None;
         }
 
-        let value = apv.value()?;
+        Some(settings_segments[attr_segments.len()..].join("."))
+    }
 
+    fn value_position(
+        apv: &AttrpathValue,
+        value: Expr,
+        indent_level: usize,
+    ) -> Option<SettingsPosition> {
         match value {
-            Expr::AttrSet(set) => {
-                let remaining = settings_segments[attr_segments.len()..].join(".");
+            Expr::With(with_expr) => match with_expr.body()? {
+                list @ Expr::List(_) => Some(Self::existing_option(apv, &list, indent_level)),
+                _ => None,
+            },
+            other => Some(Self::existing_option(apv, &other, indent_level)),
+        }
+    }
 
-                if remaining.is_empty() {
-                    return Some(SettingsPosition::ExistingOption(ExistingOption::new(
-                        text_range_to_range(apv.syntax().text_range()),
-                        text_range_to_range(set.syntax().text_range()),
-                        indent_level,
-                    )));
+    /// Position of `settings` if it is one of the names of `inherit`. The
+    /// name token is used as key, value and definition range.
+    fn inherited_option(
+        inherit: &Inherit,
+        settings: &str,
+        indent_level: usize,
+    ) -> Option<SettingsPosition> {
+        let attr = inherit.attrs().find(|attr| attr.to_string() == settings)?;
+        let range = text_range_to_range(attr.syntax().text_range());
+        Some(SettingsPosition::ExistingOption(ExistingOption::new(
+            range.clone(),
+            range.clone(),
+            range,
+            indent_level,
+            ValueKind::Inherit,
+        )))
+    }
+
+    fn existing_option(apv: &AttrpathValue, value: &Expr, indent_level: usize) -> SettingsPosition {
+        SettingsPosition::ExistingOption(ExistingOption::new(
+            text_range_to_range(apv.syntax().text_range()),
+            apv.attrpath()
+                .map_or(0..0, |path| text_range_to_range(path.syntax().text_range())),
+            text_range_to_range(value.syntax().text_range()),
+            indent_level,
+            ValueKind::from_expr(value),
+        ))
+    }
+
+    /// Returns every definition of `settings` instead of only the first one,
+    /// to detect an option defined several times in the same file.
+    #[allow(dead_code)]
+    pub fn find_all(nix_ast: &rnix::SyntaxNode, settings: &str) -> Vec<SettingsPosition> {
+        let mut found = Vec::new();
+        Self::find_all_in_node(nix_ast, settings, 0, &mut found);
+        found
+    }
+
+    /// Returns the full dotted path and position of every definition whose
+    /// path ends with `suffix`, like every `enable` under `services`.
+    #[allow(dead_code)]
+    pub fn find_by_suffix(
+        nix_ast: &rnix::SyntaxNode,
+        suffix: &str,
+    ) -> Vec<(String, SettingsPosition)> {
+        let mut found = Vec::new();
+        Self::walk_definitions(nix_ast, 0, &mut |path, pos| {
+            if path == suffix || path.ends_with(&format!(".{}", suffix)) {
+                found.push((path, pos));
+            }
+        });
+        found
+    }
+
+    /// Returns the full dotted path and definition range of every option of
+    /// the file. Attribute sets are described by the options they hold.
+    #[allow(dead_code)]
+    pub fn list_all(nix_ast: &rnix::SyntaxNode) -> Vec<(String, Range<usize>)> {
+        let mut options = Vec::new();
+        Self::walk_definitions(nix_ast, 0, &mut |path, pos| {
+            if let SettingsPosition::ExistingOption(option) = pos
+                && option.get_value_kind() != ValueKind::AttrSet
+            {
+                options.push((path, option.get_range_option().clone()));
+            }
+        });
+        options
+    }
+
+    /// Same traversal as [`SettingsPosition::list_all`], each option being
+    /// returned with its full position instead of its definition range.
+    pub fn list_all_existing(nix_ast: &rnix::SyntaxNode) -> Vec<(String, ExistingOption)> {
+        let mut options = Vec::new();
+        Self::walk_definitions(nix_ast, 0, &mut |path, pos| {
+            if let SettingsPosition::ExistingOption(option) = pos
+                && option.get_value_kind() != ValueKind::AttrSet
+            {
+                options.push((path, option));
+            }
+        });
+        options
+    }
+
+    /// Calls `visit` with the full dotted path and position of every
+    /// definition of the first attribute set found below `node`, nested sets
+    /// included.
+    fn walk_definitions(
+        node: &rnix::SyntaxNode,
+        indent_level: usize,
+        visit: &mut dyn FnMut(String, SettingsPosition),
+    ) -> bool {
+        if let Some(attr_set) = AttrSet::cast(node.clone()) {
+            Self::walk_attr_set(&attr_set, "", indent_level + 1, visit);
+            return true;
+        }
+        node.children()
+            .any(|child| Self::walk_definitions(&child, indent_level, visit))
+    }
+
+    fn walk_attr_set(
+        attr_set: &AttrSet,
+        prefix: &str,
+        indent_level: usize,
+        visit: &mut dyn FnMut(String, SettingsPosition),
+    ) {
+        for entry in attr_set.entries() {
+            let apv = match entry {
+                rnix::ast::Entry::AttrpathValue(apv) => apv,
+                rnix::ast::Entry::Inherit(inherit) => {
+                    for attr in inherit.attrs() {
+                        let name = attr.to_string();
+                        let path = match prefix {
+                            "" => name.clone(),
+                            prefix => format!("{}.{}", prefix, name),
+                        };
+                        if let Some(pos) = Self::inherited_option(&inherit, &name, indent_level) {
+                            visit(path, pos);
+                        }
+                    }
+                    continue;
                 }
+            };
+            let (Some(attrpath), Some(value)) = (apv.attrpath(), apv.value()) else {
+                continue;
+            };
+            let key: Vec<String> = attrpath.attrs().map(|a| a.to_string()).collect();
+            let path = match prefix {
+                "" => key.join("."),
+                prefix => format!("{}.{}", prefix, key.join(".")),
+            };
+            if let Some(pos) = Self::value_position(&apv, value.clone(), indent_level) {
+                visit(path.clone(), pos);
+            }
+            if let Some(set) = match value {
+                Expr::AttrSet(set) => Some(set),
+                other => conditional_body(&other),
+            } {
+                Self::walk_attr_set(&set, &path, indent_level + 1, visit);
+            }
+        }
+    }
 
-                Some(Self::localise_in_attr_set(
-                    &set,
-                    &remaining,
-                    indent_level + 1,
-                ))
+    fn find_all_in_node(
+        node: &rnix::SyntaxNode,
+        settings: &str,
+        indent_level: usize,
+        found: &mut Vec<SettingsPosition>,
+    ) -> bool {
+        if let Some(attr_set) = AttrSet::cast(node.clone()) {
+            Self::find_all_in_attr_set(&attr_set, settings, indent_level + 1, found);
+            return true;
+        }
+
+        if let Some(set) = Expr::cast(node.clone()).and_then(|expr| conditional_body(&expr)) {
+            Self::find_all_in_attr_set(&set, settings, indent_level + 1, found);
+            return true;
+        }
+
+        if let Some(apv) = AttrpathValue::cast(node.clone()) {
+            Self::find_all_in_attrpath_value(&apv, settings, indent_level, found);
+            return true;
+        }
+
+        node.children()
+            .any(|child| Self::find_all_in_node(&child, settings, indent_level, found))
+    }
+
+    fn find_all_in_attr_set(
+        attr_set: &AttrSet,
+        settings: &str,
+        indent_level: usize,
+        found: &mut Vec<SettingsPosition>,
+    ) {
+        for entry in attr_set.entries() {
+            match entry {
+                rnix::ast::Entry::AttrpathValue(apv) => {
+                    Self::find_all_in_attrpath_value(&apv, settings, indent_level, found);
+                }
+                rnix::ast::Entry::Inherit(inherit) => {
+                    found.extend(Self::inherited_option(&inherit, settings, indent_level));
+                }
             }
+        }
+    }
 
-            Expr::List(list) => Some(SettingsPosition::ExistingOption(ExistingOption::new(
-                text_range_to_range(apv.syntax().text_range()),
-                text_range_to_range(list.syntax().text_range()),
-                indent_level,
-            ))),
-
-            Expr::With(with_expr) => {
-                let inner_list = with_expr.body()?;
-                if let Expr::List(list) = inner_list {
-                    Some(SettingsPosition::ExistingOption(ExistingOption::new(
-                        text_range_to_range(apv.syntax().text_range()),
-                        text_range_to_range(list.syntax().text_range()),
-                        indent_level,
-                    )))
-                } else {
-                    None
+    fn find_all_in_attrpath_value(
+        apv: &AttrpathValue,
+        settings: &str,
+        indent_level: usize,
+        found: &mut Vec<SettingsPosition>,
+    ) {
+        let Some(remaining) = Self::match_attrpath(apv, settings) else {
+            return;
+        };
+        let Some(value) = apv.value() else {
+            return;
+        };
+
+        match value {
+            Expr::AttrSet(set) if !remaining.is_empty() => {
+                Self::find_all_in_attr_set(&set, &remaining, indent_level + 1, found);
+            }
+            other if !remaining.is_empty() => {
+                if let Some(set) = conditional_body(&other) {
+                    Self::find_all_in_attr_set(&set, &remaining, indent_level + 1, found);
+                }
+            }
+            other if remaining.is_empty() => {
+                if let Some(pos) = Self::value_position(apv, other, indent_level) {
+                    found.push(pos);
                 }
             }
+            _ => (),
+        }
+    }
+}
 
-            other => Some(SettingsPosition::ExistingOption(ExistingOption::new(
-                text_range_to_range(apv.syntax().text_range()),
-                text_range_to_range(other.syntax().text_range()),
-                indent_level,
-            ))),
+/// Searches `nix_option` in `entry_file` then, recursively, in the files listed
+/// in its `imports`. Returns the file actually defining the option with its
+/// position, or `None` when no file of the import tree defines it.
+#[allow(dead_code)]
+pub fn resolve_option_across_imports(
+    entry_file: &Path,
+    nix_option: &str,
+) -> mx::Result<Option<(PathBuf, SettingsPosition)>> {
+    let mut visited = HashSet::new();
+    resolve_in_file(entry_file, nix_option, &mut visited)
+}
+
+fn resolve_in_file(
+    file: &Path,
+    nix_option: &str,
+    visited: &mut HashSet<PathBuf>,
+) -> mx::Result<Option<(PathBuf, SettingsPosition)>> {
+    let mut path = file.canonicalize().map_err(mx::ErrorKind::IOError)?;
+    if path.is_dir() {
+        path.push("default.nix");
+    }
+    if !visited.insert(path.clone()) {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(&path).map_err(mx::ErrorKind::IOError)?;
+    let ast = rnix::Root::parse(&content).syntax();
+    if let Ok(pos @ SettingsPosition::ExistingOption(_)) = SettingsPosition::new(&ast, nix_option) {
+        return Ok(Some((path, pos)));
+    }
+
+    let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+    for import in imported_paths(&ast, &content) {
+        if let Some(found) = resolve_in_file(&dir.join(import), nix_option, visited)? {
+            return Ok(Some(found));
         }
     }
+    Ok(None)
+}
+
+/// Path literals of the `imports` list, other elements (`<nixpkgs/...>`,
+/// expressions) can not be resolved without evaluation and are skipped.
+fn imported_paths<'a>(ast: &rnix::SyntaxNode, content: &'a str) -> Vec<&'a str> {
+    let Ok(SettingsPosition::ExistingOption(imports)) = SettingsPosition::new(ast, "imports")
+    else {
+        return Vec::new();
+    };
+    if imports.get_value_kind() != ValueKind::List {
+        return Vec::new();
+    }
+    let list = &content[imports.get_range_option_value().clone()];
+    List::element_spans(list)
+        .into_iter()
+        .map(|span| &list[span])
+        .filter(|item| {
+            matches!(
+                rnix::Root::parse(item).tree().expr(),
+                Some(Expr::PathRel(_) | Expr::PathAbs(_))
+            )
+        })
+        .collect()
 }
 
 #[allow(dead_code)]
@@ -583,3 +1106,7 @@ mod v1 {
         }
     }
 }
+
+#[cfg(test)]
+#[path = "localise_option_tests.rs"]
+mod tests;
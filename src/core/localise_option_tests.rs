@@ -0,0 +1,613 @@
+/// Tests for [`SettingsPosition`].
+///
+/// # Structure
+/// - `unit` – Option lookup, value kind and line/column positions on in-memory Nix sources.
+/// - `imports` – Option lookup across files linked by `imports`.
+use super::{
+    LineCol, PositionInfo, SettingsPosition, ValueKind, resolve_option_across_imports,
+    split_option_path,
+};
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Helpers
+// ─────────────────────────────────────────────────────────────────────────────
+
+fn parse(content: &str) -> rnix::SyntaxNode {
+    rnix::Root::parse(content).syntax()
+}
+
+/// Returns the source text of the value of every definition found.
+fn values<'a>(content: &'a str, positions: &[SettingsPosition]) -> Vec<&'a str> {
+    positions
+        .iter()
+        .map(|p| match p {
+            SettingsPosition::ExistingOption(o) => &content[o.get_range_option_value().clone()],
+            SettingsPosition::NewInsertion(_) => panic!("expected an existing option"),
+        })
+        .collect()
+}
+
+/// A typical `configuration.nix`, mixing dotted keys and nested sets.
+const CONFIGURATION: &str = r#"{ config, pkgs, ... }:
+{
+  imports = [ ./hardware-configuration.nix ];
+
+  boot.loader.systemd-boot.enable = true;
+  boot.loader.efi.canTouchEfiVariables = true;
+
+  networking = {
+    hostName = "nixos";
+    networkmanager.enable = true;
+  };
+
+  time.timeZone = "Europe/Paris";
+
+  services.xserver = {
+    enable = true;
+    xkb = {
+      layout = "fr";
+    };
+  };
+
+  environment.systemPackages = with pkgs; [ vim git ];
+
+  system.stateVersion = "24.05";
+}
+"#;
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Unit tests
+// ─────────────────────────────────────────────────────────────────────────────
+mod unit {
+    use super::*;
+
+    // ── find_all ──────────────────────────────────────────────────────────────
+
+    /// Two identical assignments at the same level are both reported.
+    #[test]
+    fn find_all_same_level_duplicates() {
+        let content = "{\n  services.nginx.enable = true;\n  services.nginx.enable = false;\n}\n";
+        let found = SettingsPosition::find_all(&parse(content), "services.nginx.enable");
+        assert_eq!(values(content, &found), vec!["true", "false"]);
+    }
+
+    /// The same path reached through dotted and nested notation is reported twice.
+    #[test]
+    fn find_all_dotted_and_nested() {
+        let content = "{\n  services.nginx.enable = true;\n  services.nginx = {\n    enable = false;\n  };\n}\n";
+        let found = SettingsPosition::find_all(&parse(content), "services.nginx.enable");
+        assert_eq!(values(content, &found), vec!["true", "false"]);
+    }
+
+    /// Each definition range covers a whole `key = value;` on its own.
+    #[test]
+    fn find_all_ranges_are_independent() {
+        let content = "{\n  a.b = 1;\n  a = { b = 2; };\n}\n";
+        let found = SettingsPosition::find_all(&parse(content), "a.b");
+        let definitions: Vec<&str> = found
+            .iter()
+            .map(|p| match p {
+                SettingsPosition::ExistingOption(o) => &content[o.get_range_option().clone()],
+                SettingsPosition::NewInsertion(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(definitions, vec!["a.b = 1;", "b = 2;"]);
+    }
+
+    /// An absent option yields no definition.
+    #[test]
+    fn find_all_absent_is_empty() {
+        let content = "{\n  services.nginx.enable = true;\n}\n";
+        assert!(SettingsPosition::find_all(&parse(content), "services.apache.enable").is_empty());
+    }
+
+    /// A shorter path holding a scalar is not a definition of a longer path.
+    #[test]
+    fn find_all_ignores_scalar_prefix() {
+        let content = "{\n  services.nginx = null;\n}\n";
+        assert!(SettingsPosition::find_all(&parse(content), "services.nginx.enable").is_empty());
+    }
+
+    /// A path naming an intermediate attribute set is an existing option
+    /// whose value is the whole set, at any depth.
+    #[test]
+    fn path_naming_an_attr_set() {
+        let content = "{\n  services = {\n    nginx = {\n      enable = true;\n    };\n  };\n}\n";
+        for (settings, value) in [
+            (
+                "services",
+                "{\n    nginx = {\n      enable = true;\n    };\n  }",
+            ),
+            ("services.nginx", "{\n      enable = true;\n    }"),
+        ] {
+            let found = SettingsPosition::new(&parse(content), settings).unwrap();
+            assert_eq!(found.get_value_kind(), Some(ValueKind::AttrSet));
+            assert_eq!(values(content, &[found]), vec![value]);
+        }
+    }
+
+    // ── get_value_kind ────────────────────────────────────────────────────────
+
+    fn kind_of(value: &str) -> Option<ValueKind> {
+        let content = format!("{{\n  a.b = {};\n}}\n", value);
+        SettingsPosition::new(&parse(&content), "a.b")
+            .unwrap()
+            .get_value_kind()
+    }
+
+    /// Each kind of Nix value is reported with the matching [`ValueKind`].
+    #[test]
+    fn value_kind_per_literal() {
+        assert_eq!(kind_of("true"), Some(ValueKind::Bool));
+        assert_eq!(kind_of("false"), Some(ValueKind::Bool));
+        assert_eq!(kind_of("42"), Some(ValueKind::Int));
+        assert_eq!(kind_of("1.5"), Some(ValueKind::Float));
+        assert_eq!(kind_of("\"foo\""), Some(ValueKind::String));
+        assert_eq!(kind_of("''\n    foo\n  ''"), Some(ValueKind::String));
+        assert_eq!(kind_of("./foo.nix"), Some(ValueKind::Path));
+        assert_eq!(kind_of("<nixpkgs>"), Some(ValueKind::Path));
+        assert_eq!(kind_of("[ 1 2 ]"), Some(ValueKind::List));
+        assert_eq!(kind_of("with pkgs; [ vim ]"), Some(ValueKind::List));
+        assert_eq!(kind_of("{ c = 1; }"), Some(ValueKind::AttrSet));
+        assert_eq!(kind_of("null"), Some(ValueKind::Ident));
+        assert_eq!(kind_of("lib.mkForce true"), Some(ValueKind::Other));
+    }
+
+    /// A function call, a selection or a parenthesized expression is returned
+    /// whole as the value.
+    #[test]
+    fn compound_expression_values() {
+        let content = "{\n  services.x.package = pkgs.callPackage ./foo.nix { };\n  services.y.package = pkgs.hello;\n  services.z.package = (import ./z.nix { inherit pkgs; });\n}\n";
+        let ast = parse(content);
+        for (path, value) in [
+            ("services.x.package", "pkgs.callPackage ./foo.nix { }"),
+            ("services.y.package", "pkgs.hello"),
+            ("services.z.package", "(import ./z.nix { inherit pkgs; })"),
+        ] {
+            assert_eq!(
+                values(content, &SettingsPosition::find_all(&ast, path)),
+                vec![value]
+            );
+            assert_eq!(
+                SettingsPosition::new(&ast, path).unwrap().get_value_kind(),
+                Some(ValueKind::Other)
+            );
+        }
+    }
+
+    /// A missing option has no value, hence no kind.
+    #[test]
+    fn value_kind_absent_is_none() {
+        let content = "{\n  a.c = 1;\n}\n";
+        let pos = SettingsPosition::new(&parse(content), "a.b").unwrap();
+        assert_eq!(pos.get_value_kind(), None);
+    }
+
+    // ── to_info ───────────────────────────────────────────────────────────────
+
+    /// An existing option keeps its ranges, a new insertion gets `-1` value
+    /// bounds and its remaining path.
+    #[test]
+    fn to_info_sentinels() {
+        let content = "{\n  a.b = 1;\n}\n";
+        let ast = parse(content);
+        assert_eq!(
+            SettingsPosition::new(&ast, "a.b").unwrap().to_info(),
+            PositionInfo {
+                def_start: 4,
+                def_end: 12,
+                value_start: 10,
+                value_end: 11,
+                remaining_path: None,
+            }
+        );
+        let end = content.rfind('}').unwrap() as u32;
+        assert_eq!(
+            SettingsPosition::new(&ast, "c.d").unwrap().to_info(),
+            PositionInfo {
+                def_start: end,
+                def_end: end,
+                value_start: -1,
+                value_end: -1,
+                remaining_path: Some("c.d".to_string()),
+            }
+        );
+    }
+
+    // ── get_range_option_line_col ─────────────────────────────────────────────
+
+    /// Columns count characters, so accents before the option do not shift them.
+    #[test]
+    fn line_col_with_multibyte_chars() {
+        let content = "# Réglages généraux\n{\n  # clé née\n  x = \"é\"; a.b = 2;\n}\n";
+        assert!(rnix::Root::parse(content).errors().is_empty());
+        let pos = SettingsPosition::new(&parse(content), "a.b").unwrap();
+        let SettingsPosition::ExistingOption(option) = pos else {
+            panic!("expected an existing option");
+        };
+        assert_eq!(
+            option.get_range_option_line_col(content),
+            (
+                LineCol {
+                    line: 4,
+                    column: 12
+                },
+                LineCol {
+                    line: 4,
+                    column: 20
+                }
+            )
+        );
+    }
+
+    /// An option on the very first column of the first line starts at 1:1.
+    #[test]
+    fn line_col_is_one_based() {
+        let content = "{\na = 1;\n}\n";
+        let SettingsPosition::ExistingOption(option) =
+            SettingsPosition::new(&parse(content), "a").unwrap()
+        else {
+            panic!("expected an existing option");
+        };
+        let (start, _) = option.get_range_option_line_col(content);
+        assert_eq!(start, LineCol { line: 2, column: 1 });
+    }
+
+    // ── whitespace in attribute paths ─────────────────────────────────────────
+
+    /// Spaces and newlines around the dots of a path do not prevent a match.
+    #[test]
+    fn whitespace_around_dots() {
+        for content in [
+            "{\n  services . nginx . enable = true;\n}\n",
+            "{\n  services\n    .nginx\n    .enable = true;\n}\n",
+        ] {
+            let found = SettingsPosition::new(&parse(content), "services.nginx.enable").unwrap();
+            assert_eq!(values(content, &[found]), vec!["true"]);
+        }
+    }
+
+    // ── key range ─────────────────────────────────────────────────────────────
+
+    /// The key range covers the dotted path only, and is absent for an insertion.
+    #[test]
+    fn range_key() {
+        let content = "{\n  services.nginx.enable = true;\n}\n";
+        let found = SettingsPosition::new(&parse(content), "services.nginx.enable").unwrap();
+        assert_eq!(
+            &content[found.get_range_key().unwrap().clone()],
+            "services.nginx.enable"
+        );
+        let absent = SettingsPosition::new(&parse(content), "services.nginx.package").unwrap();
+        assert!(absent.get_range_key().is_none());
+    }
+
+    // ── indent level ──────────────────────────────────────────────────────────
+
+    fn level(content: &str, settings: &str) -> usize {
+        SettingsPosition::new(&parse(content), settings)
+            .unwrap()
+            .get_indent_level()
+    }
+
+    /// The top-level set is level 1 and each nested set adds one, for both
+    /// existing options and insertion points.
+    #[test]
+    fn indent_level_per_nesting() {
+        let content =
+            "{\n  a = 1;\n  b = {\n    c = 2;\n    d = {\n      e = 3;\n    };\n  };\n}\n";
+        assert_eq!(level(content, "a"), 1);
+        assert_eq!(level(content, "b.c"), 2);
+        assert_eq!(level(content, "b.d.e"), 3);
+        assert_eq!(level(content, "z"), 1);
+        assert_eq!(level(content, "b.z"), 2);
+        assert_eq!(level(content, "b.d.z"), 3);
+    }
+
+    // ── best partial match ────────────────────────────────────────────────────
+
+    fn insertion(content: &str, settings: &str) -> (usize, String) {
+        match SettingsPosition::new(&parse(content), settings).unwrap() {
+            SettingsPosition::NewInsertion(n) => (
+                n.get_pos_new_insertion(),
+                n.get_remaining_path().to_string(),
+            ),
+            SettingsPosition::ExistingOption(_) => panic!("expected a new insertion"),
+        }
+    }
+
+    /// The most specific branch (shortest remaining path) is chosen,
+    /// whatever the order of the candidates.
+    #[test]
+    fn insertion_prefers_most_specific_branch() {
+        for content in [
+            "{\n  services = {\n  };\n  services.nginx = {\n  };\n}\n",
+            "{\n  services.nginx = {\n  };\n  services = {\n  };\n}\n",
+        ] {
+            let (pos, remaining) = insertion(content, "services.nginx.enable");
+            assert_eq!(remaining, "enable");
+            let nginx_set = content.find("services.nginx = {").unwrap();
+            assert!(pos > nginx_set && pos < nginx_set + "services.nginx = {\n  }".len());
+        }
+    }
+
+    // ── find_by_suffix ────────────────────────────────────────────────────────
+
+    /// Every definition ending with the suffix is found, with its full path.
+    #[test]
+    fn find_by_suffix_enable() {
+        let content = "{ pkgs, ... }:\n{\n  services.nginx.enable = true;\n  services = {\n    openssh.enable = false;\n    printing = {\n      enable = true;\n    };\n  };\n  networking.networkmanager.enable = true;\n  services.xserver.enableTCP = true;\n}\n";
+        let found = SettingsPosition::find_by_suffix(&parse(content), "enable");
+        let paths: Vec<&str> = found.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "services.nginx.enable",
+                "services.openssh.enable",
+                "services.printing.enable",
+                "networking.networkmanager.enable",
+            ]
+        );
+        let positions: Vec<SettingsPosition> = found.into_iter().map(|(_, pos)| pos).collect();
+        assert_eq!(
+            values(content, &positions),
+            ["true", "false", "true", "true"]
+        );
+    }
+
+    // ── list_all ──────────────────────────────────────────────────────────────
+
+    /// Every leaf option is listed with its full path and definition range.
+    #[test]
+    fn list_all_options() {
+        let options = SettingsPosition::list_all(&parse(CONFIGURATION));
+        let paths: Vec<&str> = options.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "imports",
+                "boot.loader.systemd-boot.enable",
+                "boot.loader.efi.canTouchEfiVariables",
+                "networking.hostName",
+                "networking.networkmanager.enable",
+                "time.timeZone",
+                "services.xserver.enable",
+                "services.xserver.xkb.layout",
+                "environment.systemPackages",
+                "system.stateVersion",
+            ]
+        );
+        let (_, range) = &options[3];
+        assert_eq!(&CONFIGURATION[range.clone()], "hostName = \"nixos\";");
+    }
+
+    // ── inherit ───────────────────────────────────────────────────────────────
+
+    /// Inherited names are present options, located on their name token.
+    #[test]
+    fn inherited_names_are_present() {
+        let content = "{ lib, enable, port, config, ... }:\n{\n  inherit enable port;\n  inherit (config) services;\n  a = 1;\n}\n";
+        let ast = parse(content);
+        for name in ["enable", "port", "services"] {
+            let found = SettingsPosition::new(&ast, name).unwrap();
+            assert_eq!(found.get_value_kind(), Some(ValueKind::Inherit));
+            assert_eq!(&content[found.get_range_key().unwrap().clone()], name);
+        }
+        assert_eq!(SettingsPosition::find_all(&ast, "port").len(), 1);
+
+        let paths: Vec<String> = SettingsPosition::list_all(&ast)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, ["enable", "port", "services", "a"]);
+    }
+
+    // ── rec sets ──────────────────────────────────────────────────────────────
+
+    /// A definition inside a `rec` set is located like in a plain set.
+    #[test]
+    fn rec_set_existing_option() {
+        let content = "{\n  x = rec {\n    a = 1;\n    b = a;\n  };\n}\n";
+        let found = SettingsPosition::new(&parse(content), "x.b").unwrap();
+        assert_eq!(values(content, &[found]), vec!["a"]);
+    }
+
+    /// A new option inside a `rec` set is inserted before its closing brace.
+    #[test]
+    fn rec_set_new_insertion() {
+        let content = "{\n  x = rec {\n    a = 1;\n  };\n}\n";
+        let (pos, remaining) = insertion(content, "x.c");
+        assert_eq!(remaining, "c");
+        assert_eq!(pos, content.find("};").unwrap());
+    }
+
+    // ── string-literal keys ───────────────────────────────────────────────────
+
+    /// A dotted string key is found when quoted in the path, and an
+    /// undotted one with or without quotes.
+    #[test]
+    fn string_literal_keys() {
+        let content = "{\n  services.nginx.virtualHosts = {\n    \"example.com\".root = \"/srv/www\";\n    \"localhost\".root = \"/srv/local\";\n  };\n}\n";
+        let get = |settings| {
+            values(
+                content,
+                &[SettingsPosition::new(&parse(content), settings).unwrap()],
+            )[0]
+        };
+        assert_eq!(
+            get("services.nginx.virtualHosts.\"example.com\".root"),
+            "\"/srv/www\""
+        );
+        assert_eq!(
+            get("services.nginx.virtualHosts.\"localhost\".root"),
+            "\"/srv/local\""
+        );
+        assert_eq!(
+            get("services.nginx.virtualHosts.localhost.root"),
+            "\"/srv/local\""
+        );
+    }
+
+    /// A missing string key keeps its quotes in the remaining path, and the
+    /// dot it contains does not split it.
+    #[test]
+    fn string_literal_key_insertion() {
+        let content = "{\n  services.nginx.virtualHosts = {\n  };\n}\n";
+        let (_, remaining) = insertion(content, "services.nginx.virtualHosts.\"example.com\".root");
+        assert_eq!(remaining, "\"example.com\".root");
+    }
+
+    /// Dots inside quotes, escaped quotes included, do not split the path.
+    #[test]
+    fn split_option_path_quotes() {
+        assert_eq!(split_option_path("a.\"b.c\".d"), vec!["a", "\"b.c\"", "d"]);
+        assert_eq!(
+            split_option_path("a.\"b\\\".c\".d"),
+            vec!["a", "\"b\\\".c\"", "d"]
+        );
+        assert_eq!(split_option_path("a"), vec!["a"]);
+    }
+
+    // ── lib.mkIf ──────────────────────────────────────────────────────────────
+
+    /// A module whose whole body is guarded by `lib.mkIf`, the condition
+    /// holding an attribute set of its own.
+    const MK_IF_BODY: &str =
+        "{ config, lib, ... }:\nlib.mkIf (config.x != { }) {\n  services.x.enable = true;\n}\n";
+
+    /// Options under `config = lib.mkIf ...`, the usual module layout.
+    const MK_IF_CONFIG: &str = "{ config, lib, ... }:\n{\n  config = lib.mkIf config.x.enable {\n    services.x.enable = true;\n  };\n}\n";
+
+    /// An option inside the guarded set is found, at the level of that set.
+    #[test]
+    fn mk_if_existing_option() {
+        let ast = parse(MK_IF_BODY);
+        assert_eq!(
+            values(
+                MK_IF_BODY,
+                &SettingsPosition::find_all(&ast, "services.x.enable")
+            ),
+            vec!["true"]
+        );
+        assert_eq!(level(MK_IF_BODY, "services.x.enable"), 1);
+
+        let ast = parse(MK_IF_CONFIG);
+        assert_eq!(
+            values(
+                MK_IF_CONFIG,
+                &SettingsPosition::find_all(&ast, "config.services.x.enable")
+            ),
+            vec!["true"]
+        );
+        assert_eq!(level(MK_IF_CONFIG, "config.services.x.enable"), 2);
+    }
+
+    /// A new option is inserted at the end of the guarded set, not in the
+    /// condition.
+    #[test]
+    fn mk_if_new_insertion() {
+        let (pos, remaining) = insertion(MK_IF_BODY, "services.y.enable");
+        assert_eq!(remaining, "services.y.enable");
+        assert_eq!(pos, MK_IF_BODY.rfind('}').unwrap());
+
+        let (pos, remaining) = insertion(MK_IF_CONFIG, "config.services.y.enable");
+        assert_eq!(remaining, "services.y.enable");
+        assert_eq!(pos, MK_IF_CONFIG.find("  };").unwrap() + 2);
+    }
+
+    /// Every option of the guarded set is listed with the path of the
+    /// attribute holding the `lib.mkIf`.
+    #[test]
+    fn mk_if_list_all() {
+        let paths: Vec<String> = SettingsPosition::list_all(&parse(MK_IF_CONFIG))
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert!(paths.contains(&"config.services.x.enable".to_string()));
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Imports
+// ─────────────────────────────────────────────────────────────────────────────
+
+mod imports {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Writes `configuration.nix` importing `./services.nix`, which imports the
+    /// entry file back to form a cycle.
+    fn two_files(services: &str) -> TempDir {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        fs::write(
+            dir.path().join("configuration.nix"),
+            "{ config, pkgs, ... }:\n{\n  imports = [ ./services.nix <nixpkgs/nixos/modules/foo.nix> ];\n  networking.hostName = \"host\";\n}\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("services.nix"), services).unwrap();
+        dir
+    }
+
+    /// An option defined in the entry file is resolved there.
+    #[test]
+    fn option_in_entry_file() {
+        let dir = two_files("{\n}\n");
+        let entry = dir.path().join("configuration.nix");
+        let (path, pos) = resolve_option_across_imports(&entry, "networking.hostName")
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, entry.canonicalize().unwrap());
+        assert!(matches!(pos, SettingsPosition::ExistingOption(_)));
+    }
+
+    /// An option only defined in an imported file is resolved in that file.
+    #[test]
+    fn option_in_imported_file() {
+        let services = "{ ... }:\n{\n  imports = [ ./configuration.nix ];\n  services.nginx.enable = true;\n}\n";
+        let dir = two_files(services);
+        let (path, pos) = resolve_option_across_imports(
+            &dir.path().join("configuration.nix"),
+            "services.nginx.enable",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            path,
+            dir.path().join("services.nix").canonicalize().unwrap()
+        );
+        assert_eq!(values(services, &[pos]), vec!["true"]);
+    }
+
+    /// An import cycle terminates and reports the option as absent.
+    #[test]
+    fn missing_option_with_cycle() {
+        let dir = two_files("{\n  imports = [ ./configuration.nix ];\n}\n");
+        let found =
+            resolve_option_across_imports(&dir.path().join("configuration.nix"), "boot.foo")
+                .unwrap();
+        assert!(found.is_none());
+    }
+
+    /// Only path literals are followed: a parenthesised `import` and a path
+    /// in a comment are neither resolved nor an error.
+    #[test]
+    fn skips_expressions_and_comments() {
+        let dir = two_files("{\n  services.nginx.enable = true;\n}\n");
+        fs::write(
+            dir.path().join("configuration.nix"),
+            "{\n  imports = [\n    (import ./missing.nix)\n    ./services.nix # was ./old.nix\n  ];\n}\n",
+        )
+        .unwrap();
+        let (path, _) = resolve_option_across_imports(
+            &dir.path().join("configuration.nix"),
+            "services.nginx.enable",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            path,
+            dir.path().join("services.nix").canonicalize().unwrap()
+        );
+    }
+}
@@ -2,8 +2,31 @@ pub mod list;
 mod localise_option;
 pub mod option;
 pub mod param;
+pub mod parsed;
 pub mod transaction;
 pub mod utils;
 pub mod user;
 
 pub const TABULATION_SIZE: usize = 2;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    Spaces(usize),
+    Tabs,
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        IndentStyle::Spaces(TABULATION_SIZE)
+    }
+}
+
+impl IndentStyle {
+    pub fn repeat(&self, level: usize) -> String {
+        match self {
+            IndentStyle::Spaces(width) => " ".repeat(width * level),
+            IndentStyle::Tabs => "\t".repeat(level),
+        }
+    }
+}
@@ -1,39 +1,119 @@
 use super::transaction::file_lock::NixFile;
-use crate::core::TABULATION_SIZE;
-use crate::core::localise_option::{ExistingOption, SettingsPosition};
+use crate::core::IndentStyle;
+use crate::core::list::List;
+use crate::core::localise_option::{
+    ExistingOption, NewInsertion, SettingsPosition, ValueKind, normalize_segment, split_option_path,
+};
+use crate::core::utils::{detect_indent, escape_string_nix, is_valid_path_value, line_diff};
 use crate::mx;
+use rnix::TextSize;
+use rnix::ast::{AttrSet, Entry, Expr, HasEntry};
+use rowan::ast::AstNode;
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::str;
 
+/// Where [`Option::set_ordered`] places a new option among its siblings.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertPosition<'a> {
+    /// Before the closing brace of the set, like [`Option::set`].
+    End,
+    /// Before the first sibling whose key sorts after the new one.
+    Alphabetical,
+    /// Right after the definition of the sibling with this key.
+    AfterKey(&'a str),
+}
+
+/// Value of an option with its position, returned by [`Option::get_with_context`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionInfo {
+    /// Source text of the value.
+    pub value: String,
+    /// Byte range of the value.
+    pub value_range: Range<usize>,
+    /// Byte range of the whole `key = value;` definition.
+    pub def_range: Range<usize>,
+    /// Nesting level of the definition, the top-level set being 1.
+    pub indent_level: usize,
+}
+
+/// How much of an option path is defined, returned by [`Option::presence`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionPresence {
+    /// The option itself is defined.
+    FullyDefined,
+    /// Only a parent attribute set is defined, `missing` is the rest of the path.
+    PrefixExists { missing: String },
+    /// No part of the path is defined.
+    Absent,
+}
+
+/// Difference on one option between two versions of a file, returned by
+/// [`Option::diff_options`]. Values are their source text.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionChange {
+    Added {
+        path: String,
+        value: String,
+    },
+    Removed {
+        path: String,
+        old: String,
+    },
+    Changed {
+        path: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl OptionChange {
+    pub fn path(&self) -> &str {
+        match self {
+            OptionChange::Added { path, .. }
+            | OptionChange::Removed { path, .. }
+            | OptionChange::Changed { path, .. } => path,
+        }
+    }
+}
+
 pub struct Option<'a> {
     nix_option: &'a str,
+    indent_style: std::option::Option<IndentStyle>,
+    force: bool,
+    blank_line_before: bool,
 }
 
 impl<'a> Option<'a> {
+    fn get_pos_option_in_str(content: &str, nix_option: &str) -> mx::Result<SettingsPosition> {
+        let ast = rnix::Root::parse(content);
+        SettingsPosition::new(&ast.syntax(), nix_option)
+    }
+
     fn get_pos_option_in_file(
         nix_file: &NixFile,
         nix_option: &str,
     ) -> mx::Result<SettingsPosition> {
-        let ast = rnix::Root::parse(&nix_file.get_file_content()?);
-        SettingsPosition::new(&ast.syntax(), nix_option)
+        Self::get_pos_option_in_str(nix_file.get_file_content()?, nix_option)
     }
 
-    fn count_char_before_newline(text: &str, mut pos: usize) -> usize {
-        let bytes = text.as_bytes();
-        let mut count = 0;
-        while pos > 0 {
-            pos -= 1;
-            if bytes[pos] == b'\n' {
-                break;
-            }
-            count += 1;
-        }
-        count
+    /// Number of bytes between the start of the line and `pos`.
+    fn count_char_before_newline(text: &str, pos: usize) -> usize {
+        pos - text[..pos].rfind('\n').map_or(0, |i| i + 1)
     }
 
     pub(super) fn get_position(&self, nix_file: &NixFile) -> mx::Result<SettingsPosition> {
         Self::get_pos_option_in_file(nix_file, self.nix_option)
     }
 
+    pub(super) fn get_position_in_str(&self, content: &str) -> mx::Result<SettingsPosition> {
+        Self::get_pos_option_in_str(content, self.nix_option)
+    }
+
     #[allow(dead_code)]
     pub fn get_option(nix_file: &NixFile, nix_option: &str) -> mx::Result<ExistingOption> {
         match Self::get_pos_option_in_file(nix_file, nix_option) {
@@ -48,11 +128,482 @@ impl<'a> Option<'a> {
     pub fn new(nix_option: &'a str) -> Self {
         Option {
             nix_option: nix_option,
+            indent_style: None,
+            force: false,
+            blank_line_before: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_indent_style(mut self, indent_style: IndentStyle) -> Self {
+        self.indent_style = Some(indent_style);
+        self
+    }
+
+    /// Allows edits on a file with syntax errors, which are refused otherwise.
+    #[allow(dead_code)]
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Separates a newly inserted option from the previous entry of its set
+    /// by a blank line. The first entry of an empty set gets none.
+    #[allow(dead_code)]
+    pub fn with_blank_line_before(mut self, blank_line_before: bool) -> Self {
+        self.blank_line_before = blank_line_before;
+        self
+    }
+
+    fn check_syntax(&self, content: &str) -> mx::Result<()> {
+        if !self.force && !rnix::Root::parse(content).errors().is_empty() {
+            return Err(mx::ErrorKind::InvalidFile);
+        }
+        Ok(())
+    }
+
+    pub(super) fn indent_style_for(&self, content: &str) -> IndentStyle {
+        match self.indent_style {
+            Some(style) => style,
+            None => detect_indent(content),
+        }
+    }
+
+    /// Returns the byte range of the written value in the file content.
+    pub fn set(&self, nix_file: &mut NixFile, option_value: &str) -> mx::Result<Range<usize>> {
+        self.set_in_str(nix_file.get_mut_file_content()?, option_value)
+    }
+
+    /// Like [`Option::set`], but when both the current and the new value are
+    /// attribute sets, the keys of `option_value` are set in the existing set
+    /// and its other keys are kept. Returns the byte range of the set.
+    /// A new set holding anything else than `key = value;` definitions, like
+    /// `inherit foo;`, can not be merged and fails with `InvalidArgument`.
+    #[allow(dead_code)]
+    pub fn merge(&self, nix_file: &mut NixFile, option_value: &str) -> mx::Result<Range<usize>> {
+        self.merge_in_str(nix_file.get_mut_file_content()?, option_value)
+    }
+
+    /// Same as [`Option::merge`] on an in-memory content.
+    pub fn merge_in_str(
+        &self,
+        content: &mut String,
+        option_value: &str,
+    ) -> mx::Result<Range<usize>> {
+        let existing_set = matches!(
+            self.get_position_in_str(content)?,
+            SettingsPosition::ExistingOption(option) if option.get_value_kind() == ValueKind::AttrSet
+        );
+        let root = rnix::Root::parse(option_value);
+        let new_set = match root.tree().expr() {
+            Some(Expr::AttrSet(set)) if root.errors().is_empty() && set.rec_token().is_none() => {
+                set
+            }
+            _ => return self.set_in_str(content, option_value),
+        };
+        if !existing_set {
+            return self.set_in_str(content, option_value);
+        }
+
+        let mut leaves = Vec::new();
+        for entry in new_set.entries() {
+            let Entry::AttrpathValue(apv) = entry else {
+                return Err(mx::ErrorKind::InvalidArgument(option_value.to_string()));
+            };
+            let (Some(path), Some(value)) = (apv.attrpath(), apv.value()) else {
+                return Err(mx::ErrorKind::InvalidArgument(option_value.to_string()));
+            };
+            leaves.push((path.to_string(), value.to_string()));
+        }
+        let mut builder = OptionBuilder::new(self.nix_option);
+        builder.indent_style = self.indent_style;
+        leaves
+            .iter()
+            .fold(builder, |builder, (leaf, value)| builder.leaf(leaf, value))
+            .build_in_str(content)
+    }
+
+    /// Sets the option to the path `value`, either a path literal or a quoted
+    /// path. Fails with `InvalidArgument` if `value` is not a valid path.
+    #[allow(dead_code)]
+    pub fn set_path(&self, nix_file: &mut NixFile, value: &str) -> mx::Result<Range<usize>> {
+        self.set_path_in_str(nix_file.get_mut_file_content()?, value)
+    }
+
+    /// Same as [`Option::set_path`] on an in-memory content.
+    pub fn set_path_in_str(&self, content: &mut String, value: &str) -> mx::Result<Range<usize>> {
+        if !is_valid_path_value(value) {
+            return Err(mx::ErrorKind::InvalidArgument(format!(
+                "{} is not a valid Nix path",
+                value
+            )));
+        }
+        self.set_in_str(content, value)
+    }
+
+    /// Sets the option to the string `raw_value`, quoting and escaping it.
+    #[allow(dead_code)]
+    pub fn set_string(&self, nix_file: &mut NixFile, raw_value: &str) -> mx::Result<Range<usize>> {
+        self.set_string_in_str(nix_file.get_mut_file_content()?, raw_value)
+    }
+
+    /// Same as [`Option::set_string`] on an in-memory content.
+    pub fn set_string_in_str(
+        &self,
+        content: &mut String,
+        raw_value: &str,
+    ) -> mx::Result<Range<usize>> {
+        self.set_in_str(content, &escape_string_nix(raw_value))
+    }
+
+    /// Sets the option like [`Option::set`] and writes `comment` as `#` lines
+    /// right above its definition, at the same indentation. The comment is not
+    /// duplicated if the definition is already preceded by it.
+    #[allow(dead_code)]
+    pub fn set_with_comment(
+        &self,
+        nix_file: &mut NixFile,
+        option_value: &str,
+        comment: std::option::Option<&str>,
+    ) -> mx::Result<Range<usize>> {
+        let content = nix_file.get_mut_file_content()?;
+        let Some(comment) = comment else {
+            return self.set_in_str(content, option_value);
+        };
+
+        // Path of the first line written: the option itself when it exists,
+        // otherwise the outermost key created by the insertion.
+        let written_key = match Self::get_pos_option_in_str(content, self.nix_option)? {
+            SettingsPosition::ExistingOption(_) => self.nix_option.to_string(),
+            SettingsPosition::NewInsertion(pos_insert) => {
+                let remaining = pos_insert.get_remaining_path();
+                let prefix = &self.nix_option[..self.nix_option.len() - remaining.len()];
+                format!("{}{}", prefix, split_option_path(remaining)[0])
+            }
+        };
+        let range = self.set_in_str(content, option_value)?;
+
+        let SettingsPosition::ExistingOption(written) =
+            Self::get_pos_option_in_str(content, &written_key)?
+        else {
+            return Err(mx::ErrorKind::OptionNotFound);
+        };
+        let def_start = written.get_range_option().start;
+        let line_start = content[..def_start].rfind('\n').map_or(0, |i| i + 1);
+        let line = &content[line_start..def_start];
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let comment_lines: String = comment
+            .lines()
+            .map(|l| format!("{}# {}\n", indent, l))
+            .collect();
+
+        let above = &content[..line_start];
+        if above.ends_with(&comment_lines)
+            && above[..above.len() - comment_lines.len()]
+                .chars()
+                .last()
+                .is_none_or(|c| c == '\n')
+        {
+            return Ok(range);
+        }
+        content.insert_str(line_start, &comment_lines);
+        Ok(range.start + comment_lines.len()..range.end + comment_lines.len())
+    }
+
+    /// Sets the option only if it is not already defined, leaving a customized
+    /// value alone. Returns `true` if the option was written.
+    #[allow(dead_code)]
+    pub fn set_if_absent(&self, nix_file: &mut NixFile, option_value: &str) -> mx::Result<bool> {
+        match self.get_position(nix_file)? {
+            SettingsPosition::ExistingOption(_) => Ok(false),
+            SettingsPosition::NewInsertion(_) => {
+                self.set(nix_file, option_value)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Moves the value of `old_option` to `new_option`. Returns `false` if
+    /// `old_option` is not defined.
+    #[allow(dead_code)]
+    pub fn rename(nix_file: &mut NixFile, old_option: &str, new_option: &str) -> mx::Result<bool> {
+        let old = Option::new(old_option);
+        let value = match old.get(nix_file) {
+            Ok(value) => value.to_string(),
+            Err(mx::ErrorKind::OptionNotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        old.set_option_to_default(nix_file)?;
+        Option::new(new_option).set(nix_file, &value)?;
+        Ok(true)
+    }
+
+    /// Sets every `(option, value)` pair in order. Each edit is applied to the
+    /// result of the previous ones, so a later option may land in a block
+    /// created by an earlier one. The file is left untouched if any edit fails.
+    pub fn set_many<K, V>(nix_file: &mut NixFile, options: &[(K, V)]) -> mx::Result<()>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut content = nix_file.get_file_content()?.clone();
+        for (nix_option, option_value) in options {
+            Option::new(nix_option.as_ref()).set_in_str(&mut content, option_value.as_ref())?;
+        }
+        *nix_file.get_mut_file_content()? = content;
+        Ok(())
+    }
+
+    /// Sets every dotted path of `options` to its raw Nix value, like
+    /// [`Option::set_many`] in the key order of the map.
+    #[allow(dead_code)]
+    pub fn set_from_map(
+        nix_file: &mut NixFile,
+        options: &BTreeMap<String, String>,
+    ) -> mx::Result<()> {
+        let options: Vec<(&String, &String)> = options.iter().collect();
+        Self::set_many(nix_file, &options)
+    }
+
+    /// Compares the options defined by two versions of a file: each option
+    /// added, removed or whose value changed is returned, sorted by path.
+    /// Formatting and comments are ignored.
+    #[allow(dead_code)]
+    pub fn diff_options(old_content: &str, new_content: &str) -> mx::Result<Vec<OptionChange>> {
+        let old = Self::option_values(old_content)?;
+        let mut new = Self::option_values(new_content)?;
+        let mut changes = Vec::new();
+        for (path, old_value) in old {
+            match new.remove(&path) {
+                None => changes.push(OptionChange::Removed {
+                    path,
+                    old: old_value,
+                }),
+                Some(new_value) if Self::tokens(&old_value) != Self::tokens(&new_value) => changes
+                    .push(OptionChange::Changed {
+                        path,
+                        old: old_value,
+                        new: new_value,
+                    }),
+                Some(_) => (),
+            }
+        }
+        changes.extend(
+            new.into_iter()
+                .map(|(path, value)| OptionChange::Added { path, value }),
+        );
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(changes)
+    }
+
+    /// Source text of the value of every option of `content`, by full path.
+    fn option_values(content: &str) -> mx::Result<BTreeMap<String, String>> {
+        let root = rnix::Root::parse(content);
+        if !root.errors().is_empty() {
+            return Err(mx::ErrorKind::InvalidFile);
+        }
+        Ok(SettingsPosition::list_all_existing(&root.syntax())
+            .into_iter()
+            .map(|(path, option)| {
+                let value = content[option.get_range_option_value().clone()].to_string();
+                (path, value)
+            })
+            .collect())
+    }
+
+    /// Tokens of `value` without whitespace and comments.
+    fn tokens(value: &str) -> Vec<String> {
+        rnix::Root::parse(value)
+            .syntax()
+            .descendants_with_tokens()
+            .filter_map(|element| element.into_token())
+            .filter(|token| !token.kind().is_trivia())
+            .map(|token| token.text().to_string())
+            .collect()
+    }
+
+    /// Exports every option of the file as a JSON object: nested sets become
+    /// nested objects, lists arrays of their raw elements and any other value
+    /// its raw source text. Quoted keys like `"example.com"` are used as is,
+    /// without their quotes.
+    #[allow(dead_code)]
+    pub fn export_json(nix_file: &NixFile) -> mx::Result<String> {
+        Self::export_json_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::export_json`] on an in-memory content.
+    pub fn export_json_in_str(content: &str) -> mx::Result<String> {
+        let root = rnix::Root::parse(content);
+        if !root.errors().is_empty() {
+            return Err(mx::ErrorKind::InvalidFile);
+        }
+        let mut json = serde_json::Map::new();
+        for (path, option) in SettingsPosition::list_all_existing(&root.syntax()) {
+            let text = content
+                .get(option.get_range_option_value().clone())
+                .ok_or(mx::ErrorKind::InvalidFile)?;
+            let value = match option.get_value_kind() {
+                ValueKind::List => serde_json::Value::Array(
+                    List::element_spans(text)
+                        .into_iter()
+                        .map(|span| serde_json::Value::String(text[span].to_string()))
+                        .collect(),
+                ),
+                _ => serde_json::Value::String(text.to_string()),
+            };
+
+            let segments = split_option_path(&path);
+            let (leaf, parents) = segments.split_last().ok_or(mx::ErrorKind::InvalidFile)?;
+            let mut object = &mut json;
+            for segment in parents {
+                let entry = object
+                    .entry(normalize_segment(segment))
+                    .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+                if !entry.is_object() {
+                    *entry = serde_json::Value::Object(serde_json::Map::new());
+                }
+                object = entry.as_object_mut().ok_or(mx::ErrorKind::InvalidFile)?;
+            }
+            object.insert(normalize_segment(leaf).to_string(), value);
         }
+        serde_json::to_string(&json).map_err(mx::ErrorKind::ParseError)
     }
 
-    pub fn set(&self, nix_file: &mut NixFile, option_value: &str) -> mx::Result<&Self> {
-        match Self::get_pos_option_in_file(&nix_file, self.nix_option)? {
+    /// Returns the diff that [`Option::set`] would apply, without modifying the file.
+    #[allow(dead_code)]
+    pub fn preview_set(&self, nix_file: &NixFile, option_value: &str) -> mx::Result<String> {
+        let old = nix_file.get_file_content()?;
+        let mut new = old.clone();
+        self.set_in_str(&mut new, option_value)?;
+        Ok(line_diff(old, &new))
+    }
+
+    /// Like [`Option::set`], a new option being placed according to `position`.
+    /// Positions which can not be honoured, like a sibling on the line of
+    /// another one, fall back to [`InsertPosition::End`].
+    #[allow(dead_code)]
+    pub fn set_ordered(
+        &self,
+        nix_file: &mut NixFile,
+        option_value: &str,
+        position: InsertPosition,
+    ) -> mx::Result<Range<usize>> {
+        self.set_ordered_in_str(nix_file.get_mut_file_content()?, option_value, position)
+    }
+
+    /// Same as [`Option::set_ordered`] on an in-memory content.
+    pub fn set_ordered_in_str(
+        &self,
+        content: &mut String,
+        option_value: &str,
+        position: InsertPosition,
+    ) -> mx::Result<Range<usize>> {
+        self.check_syntax(content)?;
+        let SettingsPosition::NewInsertion(pos_insert) =
+            Self::get_pos_option_in_str(content, self.nix_option)?
+        else {
+            return self.set_in_str(content, option_value);
+        };
+        let Some(line_start) = Self::ordered_insert_offset(content, &pos_insert, position) else {
+            return self.set_in_str(content, option_value);
+        };
+
+        let indent = pos_insert.get_indent_level().max(1);
+        let style = self.indent_style_for(content);
+        let (option_text, offset) = Self::write_option(
+            &split_option_path(pos_insert.get_remaining_path()),
+            indent,
+            option_value,
+            style,
+        );
+        // The text ends with the indentation of the closing brace, the
+        // following sibling keeps its own.
+        let closing_indent = style.repeat(indent - 1);
+        let option_text = option_text
+            .strip_suffix(&closing_indent)
+            .unwrap_or(&option_text);
+        content.insert_str(line_start, option_text);
+        let start = line_start + offset;
+        Ok(start..start + option_value.len())
+    }
+
+    /// Start of the line before which the new option goes, `None` to insert
+    /// at the end of the set.
+    fn ordered_insert_offset(
+        content: &str,
+        pos_insert: &NewInsertion,
+        position: InsertPosition,
+    ) -> std::option::Option<usize> {
+        let ast = rnix::Root::parse(content).syntax();
+        let closing = ast
+            .token_at_offset(TextSize::from(pos_insert.get_pos_new_insertion() as u32))
+            .right_biased()?;
+        let attr_set = closing.parent_ancestors().find_map(AttrSet::cast)?;
+        let siblings: Vec<(String, Range<usize>)> = attr_set
+            .entries()
+            .filter_map(|entry| match entry {
+                Entry::AttrpathValue(apv) => {
+                    let range = apv.syntax().text_range();
+                    Some((
+                        apv.attrpath()?.to_string(),
+                        range.start().into()..range.end().into(),
+                    ))
+                }
+                Entry::Inherit(_) => None,
+            })
+            .collect();
+
+        let is_blank = |text: &str| text.trim().is_empty();
+        match position {
+            InsertPosition::End => None,
+            InsertPosition::Alphabetical => {
+                let new_key = pos_insert.get_remaining_path();
+                let (_, range) = siblings.iter().find(|(key, _)| key.as_str() > new_key)?;
+                let line_start =
+                    range.start - Self::count_char_before_newline(content, range.start);
+                is_blank(&content[line_start..range.start]).then_some(line_start)
+            }
+            InsertPosition::AfterKey(after) => {
+                let (_, range) = siblings.iter().find(|(key, _)| key == after)?;
+                let line_end = content[range.end..].find('\n')? + range.end;
+                is_blank(&content[range.end..line_end]).then_some(line_end + 1)
+            }
+        }
+    }
+
+    /// Returns the text to insert and the offset of the value in it.
+    fn write_option(
+        path: &[&str],
+        indent: usize,
+        option_value: &str,
+        style: IndentStyle,
+    ) -> (String, usize) {
+        if let Some((key, path)) = path.split_first() {
+            if path.is_empty() {
+                let prefix = format!("{}{} = ", style.repeat(indent), key);
+                return (
+                    format!(
+                        "{}{};\n{}",
+                        prefix,
+                        &option_value,
+                        style.repeat(indent - 1usize)
+                    ),
+                    prefix.len(),
+                );
+            } else {
+                let prefix = format!("{}{} = {{\n", style.repeat(indent), key);
+                let (inner, offset) = Self::write_option(path, indent + 1, option_value, style);
+                let result = format!("{}{}}};\n{}", prefix, inner, style.repeat(indent - 1usize));
+                return (result, prefix.len() + offset);
+            }
+        }
+        return (String::new(), 0);
+    }
+
+    /// Same as [`Option::set`] on an in-memory content, without any file access.
+    pub fn set_in_str(&self, content: &mut String, option_value: &str) -> mx::Result<Range<usize>> {
+        self.check_syntax(content)?;
+        match Self::get_pos_option_in_str(content, self.nix_option)? {
             SettingsPosition::NewInsertion(pos_insert) => {
                 let indent = if pos_insert.get_indent_level() > 0usize {
                     (pos_insert.get_indent_level()) as usize
@@ -61,78 +612,427 @@ impl<'a> Option<'a> {
                 };
 
                 let insert_pos = pos_insert.get_pos_new_insertion();
-                let number_previous_indent =
-                    Self::count_char_before_newline(&nix_file.get_mut_file_content()?, insert_pos);
-
-                fn write_option<'a>(
-                    mut path: str::Split<'a, char>,
-                    indent: usize,
-                    option_value: &str,
-                ) -> String {
-                    if let Some(key) = path.next() {
-                        let remaining = path.clone().count();
-                        if remaining == 0 {
-                            return format!(
-                                "{}{} = {};\n{}",
-                                " ".repeat(TABULATION_SIZE * indent),
-                                key,
-                                &option_value,
-                                " ".repeat(TABULATION_SIZE * (indent - 1usize))
-                            );
-                        } else {
-                            let prefix =
-                                format!("{}{} = {{\n", " ".repeat(TABULATION_SIZE * indent), key);
-                            let inner = write_option(path, indent + 1, option_value);
-                            let result = format!(
-                                "{}{}}};\n{}",
-                                prefix,
-                                inner,
-                                " ".repeat(TABULATION_SIZE * (indent - 1usize))
-                            );
-                            return result;
-                        }
-                    }
-                    return String::new();
+                let number_previous_indent = Self::count_char_before_newline(content, insert_pos);
+                // A set written on one line has code before the closing brace:
+                // the option then starts on a new line.
+                let line_start = insert_pos - number_previous_indent;
+                let (begin, mut line_break) = if content[line_start..insert_pos].trim().is_empty() {
+                    (line_start, "")
+                } else {
+                    (
+                        content[..insert_pos].trim_end_matches([' ', '\t']).len(),
+                        "\n",
+                    )
+                };
+                let before = &content[..begin];
+                if self.blank_line_before
+                    && !before.trim_end().ends_with('{')
+                    && !before.ends_with("\n\n")
+                {
+                    line_break = if line_break.is_empty() { "\n" } else { "\n\n" };
                 }
 
-                let option_value = write_option(
-                    pos_insert.get_remaining_path().split('.'),
+                let (option_text, offset) = Self::write_option(
+                    &split_option_path(pos_insert.get_remaining_path()),
                     indent,
                     option_value,
+                    self.indent_style_for(content),
                 );
-                let begin = insert_pos - number_previous_indent;
-
-                nix_file
-                    .get_mut_file_content()?
-                    .replace_range(begin..insert_pos, &option_value);
+                content.replace_range(begin..insert_pos, &format!("{line_break}{option_text}"));
+                let start = begin + line_break.len() + offset;
+                Ok(start..start + option_value.len())
             }
             SettingsPosition::ExistingOption(exist_pos) => {
+                if exist_pos.get_value_kind() == ValueKind::Inherit {
+                    return Err(mx::ErrorKind::InvalidArgument(format!(
+                        "{} is inherited and has no value to replace",
+                        self.nix_option
+                    )));
+                }
                 let range_value = exist_pos.get_range_option_value().clone();
-                nix_file
-                    .get_mut_file_content()?
-                    .replace_range(range_value, &option_value);
+                let start = range_value.start;
+                content.replace_range(range_value, &option_value);
+                Ok(start..start + option_value.len())
             }
         }
-        return Ok(&self);
     }
 
-    pub fn get(&self, nix_file: &'a NixFile) -> mx::Result<&'a str> {
-        match Self::get_pos_option_in_file(nix_file, self.nix_option)? {
+    /// Sets the option to a `''` string holding `lines`, indented one level
+    /// deeper than the option itself. Empty lines are kept empty.
+    #[allow(dead_code)]
+    pub fn set_multiline(
+        &self,
+        nix_file: &mut NixFile,
+        lines: &[&str],
+    ) -> mx::Result<Range<usize>> {
+        self.set_multiline_in_str(nix_file.get_mut_file_content()?, lines)
+    }
+
+    /// Same as [`Option::set_multiline`] on an in-memory content.
+    pub fn set_multiline_in_str(
+        &self,
+        content: &mut String,
+        lines: &[&str],
+    ) -> mx::Result<Range<usize>> {
+        let style = self.indent_style_for(content);
+        let option_indent = match Self::get_pos_option_in_str(content, self.nix_option)? {
             SettingsPosition::ExistingOption(option) => {
-                Ok(&nix_file.get_file_content()?[option.get_range_option_value().clone()])
+                let start = option.get_range_option().start;
+                let line_start = content[..start].rfind('\n').map_or(0, |i| i + 1);
+                let line = &content[line_start..start];
+                line[..line.len() - line.trim_start().len()].to_string()
+            }
+            SettingsPosition::NewInsertion(pos_insert) => {
+                let depth = pos_insert.get_indent_level().max(1)
+                    + split_option_path(pos_insert.get_remaining_path()).len()
+                    - 1;
+                style.repeat(depth)
+            }
+        };
+        // Nix only strips spaces from `''` strings, tabs would end up in the value.
+        let unit = match style {
+            IndentStyle::Tabs => IndentStyle::default(),
+            spaces => spaces,
+        }
+        .repeat(1);
+        let option_indent = option_indent.replace('\t', &unit);
+        let body_indent = format!("{}{}", option_indent, unit);
+
+        let mut value = String::from("''\n");
+        for line in lines {
+            if !line.is_empty() {
+                value.push_str(&body_indent);
+                value.push_str(line);
+            }
+            value.push('\n');
+        }
+        value.push_str(&option_indent);
+        value.push_str("''");
+        self.set_in_str(content, &value)
+    }
+
+    /// Returns the lines of a `''` string option, without the delimiters and
+    /// with the common indentation removed. Escapes are not interpreted.
+    #[allow(dead_code)]
+    pub fn get_multiline(&self, nix_file: &NixFile) -> mx::Result<Vec<String>> {
+        Self::dedent_lines(self.get(nix_file)?)
+    }
+
+    /// Splits a `''` string into its lines, dropping a blank first and last
+    /// line and the common indentation.
+    fn dedent_lines(value: &str) -> mx::Result<Vec<String>> {
+        let inner = value
+            .strip_prefix("''")
+            .and_then(|v| v.strip_suffix("''"))
+            .ok_or(mx::ErrorKind::InvalidNixString)?;
+
+        let mut lines: Vec<&str> = inner.split('\n').collect();
+        if lines.first().is_some_and(|l| l.trim().is_empty()) {
+            lines.remove(0);
+        }
+        if lines.last().is_some_and(|l| l.trim().is_empty()) {
+            lines.pop();
+        }
+
+        let indent = lines
+            .iter()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.len() - l.trim_start_matches(' ').len())
+            .min()
+            .unwrap_or(0);
+        Ok(lines
+            .iter()
+            .map(|l| {
+                if l.trim().is_empty() {
+                    String::new()
+                } else {
+                    l[indent..].to_string()
+                }
+            })
+            .collect())
+    }
+
+    /// Reads a string option as its logical value: delimiters stripped,
+    /// escapes resolved and `''` strings dedented.
+    #[allow(dead_code)]
+    pub fn get_string(&self, nix_file: &NixFile) -> mx::Result<String> {
+        self.get_string_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::get_string`] on an in-memory content.
+    pub fn get_string_in_str(&self, content: &str) -> mx::Result<String> {
+        let value = self.get_in_str(content)?;
+        if ValueKind::of_source(value) != ValueKind::String {
+            return Err(self.type_mismatch("a string"));
+        }
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            return Ok(Self::unescape_string(&value[1..value.len() - 1]));
+        }
+        if value.len() >= 4 && value.starts_with("''") && value.ends_with("''") {
+            // Nix keeps the line break before the closing `''` when the last
+            // line holds only indentation.
+            let inner = &value[2..value.len() - 2];
+            let trailing_newline = inner
+                .rsplit_once('\n')
+                .is_some_and(|(_, last)| last.trim().is_empty());
+            let mut text = Self::dedent_lines(value)?.join("\n");
+            if trailing_newline {
+                text.push('\n');
             }
+            return Ok(Self::unescape_indented_string(&text));
+        }
+        Err(self.type_mismatch("a string"))
+    }
+
+    fn unescape_char(c: std::option::Option<char>, out: &mut String) {
+        match c {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+
+    fn unescape_string(inner: &str) -> String {
+        let mut out = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                Self::unescape_char(chars.next(), &mut out);
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    /// Resolves the `'''`, `''$` and `''\x` escapes of a `''` string.
+    fn unescape_indented_string(text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(pos) = rest.find("''") {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + 2..];
+            let mut chars = after.chars();
+            match chars.next() {
+                Some('\'') => out.push_str("''"),
+                Some('$') => out.push('$'),
+                Some('\\') => Self::unescape_char(chars.next(), &mut out),
+                _ => {
+                    out.push_str("''");
+                    rest = after;
+                    continue;
+                }
+            }
+            rest = chars.as_str();
+        }
+        out.push_str(rest);
+        out
+    }
+
+    pub fn get(&self, nix_file: &'a NixFile) -> mx::Result<&'a str> {
+        self.get_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::get`] on an in-memory content.
+    pub fn get_in_str<'b>(&self, content: &'b str) -> mx::Result<&'b str> {
+        match Self::get_pos_option_in_str(content, self.nix_option)? {
+            SettingsPosition::ExistingOption(option) => content
+                .get(option.get_range_option_value().clone())
+                .ok_or(mx::ErrorKind::InvalidFile),
             SettingsPosition::NewInsertion(_) => Err(mx::ErrorKind::OptionNotFound),
         }
     }
 
+    /// Returns `false` if writing `new_value` would change the kind of the
+    /// current value, like a boolean replaced by a string. Advisory only:
+    /// [`Option::set`] does not check it.
+    ///
+    /// An absent option, or a value whose kind is only known once evaluated
+    /// (an identifier, a function call...), is always compatible.
+    #[allow(dead_code)]
+    pub fn check_value_compatible(&self, nix_file: &NixFile, new_value: &str) -> mx::Result<bool> {
+        self.check_value_compatible_in_str(nix_file.get_file_content()?, new_value)
+    }
+
+    /// Same as [`Option::check_value_compatible`] on an in-memory content.
+    pub fn check_value_compatible_in_str(
+        &self,
+        content: &str,
+        new_value: &str,
+    ) -> mx::Result<bool> {
+        let Some(current) = self.get_position_in_str(content)?.get_value_kind() else {
+            return Ok(true);
+        };
+        let new = ValueKind::of_source(new_value);
+        Ok(!current.is_literal() || !new.is_literal() || current == new)
+    }
+
+    /// Returns the value of the option with its ranges and indent level, or
+    /// `None` if the option is not defined.
+    #[allow(dead_code)]
+    pub fn get_with_context(
+        &self,
+        nix_file: &NixFile,
+    ) -> mx::Result<std::option::Option<OptionInfo>> {
+        self.get_with_context_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::get_with_context`] on an in-memory content.
+    pub fn get_with_context_in_str(
+        &self,
+        content: &str,
+    ) -> mx::Result<std::option::Option<OptionInfo>> {
+        let SettingsPosition::ExistingOption(option) = self.get_position_in_str(content)? else {
+            return Ok(None);
+        };
+        let value_range = option.get_range_option_value().clone();
+        Ok(Some(OptionInfo {
+            value: content
+                .get(value_range.clone())
+                .ok_or(mx::ErrorKind::InvalidFile)?
+                .to_string(),
+            value_range,
+            def_range: option.get_range_option().clone(),
+            indent_level: option.get_indent_level(),
+        }))
+    }
+
+    /// Returns `true` if the option is defined in the file, `false` if it is
+    /// left to its module default.
+    #[allow(dead_code)]
+    pub fn is_explicitly_set(&self, nix_file: &NixFile) -> mx::Result<bool> {
+        Ok(matches!(
+            self.get_position(nix_file)?,
+            SettingsPosition::ExistingOption(_)
+        ))
+    }
+
+    /// Tells whether the option is defined, only through a parent attribute
+    /// set, or not at all.
+    #[allow(dead_code)]
+    pub fn presence(&self, nix_file: &NixFile) -> mx::Result<OptionPresence> {
+        self.presence_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::presence`] on an in-memory content.
+    pub fn presence_in_str(&self, content: &str) -> mx::Result<OptionPresence> {
+        Ok(match self.get_position_in_str(content)? {
+            SettingsPosition::ExistingOption(_) => OptionPresence::FullyDefined,
+            SettingsPosition::NewInsertion(insertion) => {
+                let missing = insertion.get_remaining_path();
+                if split_option_path(missing).len() < split_option_path(self.nix_option).len() {
+                    OptionPresence::PrefixExists {
+                        missing: missing.to_string(),
+                    }
+                } else {
+                    OptionPresence::Absent
+                }
+            }
+        })
+    }
+
+    /// Returns the direct `key = value` definitions of an attribute set
+    /// option, each value as its source text (`{ ... }` for a nested set).
+    #[allow(dead_code)]
+    pub fn get_attrset(&self, nix_file: &NixFile) -> mx::Result<Vec<(String, String)>> {
+        self.get_attrset_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::get_attrset`] on an in-memory content.
+    pub fn get_attrset_in_str(&self, content: &str) -> mx::Result<Vec<(String, String)>> {
+        let value = self.get_in_str(content)?;
+        let Some(Expr::AttrSet(attr_set)) = rnix::Root::parse(value).tree().expr() else {
+            return Err(mx::ErrorKind::OptionIsNotAttrSet);
+        };
+        Ok(attr_set
+            .entries()
+            .filter_map(|entry| match entry {
+                Entry::AttrpathValue(apv) => {
+                    Some((apv.attrpath()?.to_string(), apv.value()?.to_string()))
+                }
+                Entry::Inherit(_) => None,
+            })
+            .collect())
+    }
+
+    fn numeric_text(&self, content: &str) -> mx::Result<String> {
+        // `-1` is a negation applied to the literal, possibly spaced.
+        Ok(self.get_in_str(content)?.split_whitespace().collect())
+    }
+
+    fn type_mismatch(&self, expected: &str) -> mx::ErrorKind {
+        mx::ErrorKind::OptionTypeMismatch(format!("{} is not {}", self.nix_option, expected))
+    }
+
+    /// Reads a `true` / `false` option. Anything else, even the string
+    /// `"true"`, is a type mismatch.
+    #[allow(dead_code)]
+    pub fn get_as_bool(&self, nix_file: &NixFile) -> mx::Result<bool> {
+        self.get_as_bool_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::get_as_bool`] on an in-memory content.
+    pub fn get_as_bool_in_str(&self, content: &str) -> mx::Result<bool> {
+        match self.get_in_str(content)? {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(self.type_mismatch("a boolean")),
+        }
+    }
+
+    /// Reads an integer option, like `42` or `-1`.
+    #[allow(dead_code)]
+    pub fn get_as_i64(&self, nix_file: &NixFile) -> mx::Result<i64> {
+        self.get_as_i64_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::get_as_i64`] on an in-memory content.
+    pub fn get_as_i64_in_str(&self, content: &str) -> mx::Result<i64> {
+        self.numeric_text(content)?
+            .parse()
+            .map_err(|_| self.type_mismatch("an integer"))
+    }
+
+    /// Reads a numeric option, like `2.5`, `-2`, or `1.5e3`.
+    #[allow(dead_code)]
+    pub fn get_as_f64(&self, nix_file: &NixFile) -> mx::Result<f64> {
+        self.get_as_f64_in_str(nix_file.get_file_content()?)
+    }
+
+    /// Same as [`Option::get_as_f64`] on an in-memory content.
+    pub fn get_as_f64_in_str(&self, content: &str) -> mx::Result<f64> {
+        let text = self.numeric_text(content)?;
+        let digits = text.trim_start_matches('-');
+        if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            return Err(self.type_mismatch("a number"));
+        }
+        text.parse().map_err(|_| self.type_mismatch("a number"))
+    }
+
+    /// Like [`Option::get`], but an undefined option is `Ok(None)` so that
+    /// `Err` only reports a file which can not be read or parsed.
+    #[allow(dead_code)]
+    pub fn try_get(&self, nix_file: &'a NixFile) -> mx::Result<std::option::Option<&'a str>> {
+        match self.get(nix_file) {
+            Ok(value) => Ok(Some(value)),
+            Err(mx::ErrorKind::OptionNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn set_option_to_default(&self, nix_file: &mut NixFile) -> mx::Result<bool> {
-        match Self::get_pos_option_in_file(nix_file, self.nix_option)? {
+        self.set_option_to_default_in_str(nix_file.get_mut_file_content()?)
+    }
+
+    /// Same as [`Option::set_option_to_default`] on an in-memory content.
+    pub fn set_option_to_default_in_str(&self, content: &mut String) -> mx::Result<bool> {
+        self.check_syntax(content)?;
+        match Self::get_pos_option_in_str(content, self.nix_option)? {
             SettingsPosition::ExistingOption(option) => {
-                nix_file
-                    .get_mut_file_content()?
-                    .replace_range(option.get_range_option().clone(), "");
-                let content = nix_file.get_mut_file_content()?;
-                let start = option.get_range_option().start - 1;
+                content.replace_range(option.get_range_option().clone(), "");
+                let start = option.get_range_option().start;
 
                 // Trouver jusqu'où remonter en une seule passe
                 let trim_start = content[..start]
@@ -147,6 +1047,37 @@ impl<'a> Option<'a> {
         }
     }
 
+    /// Like [`Option::set_option_to_default`], also removing the run of `#`
+    /// lines right above the definition. The run stops at a blank line or any
+    /// other code, so unrelated comments are kept.
+    #[allow(dead_code)]
+    pub fn set_option_to_default_with_comments(&self, nix_file: &mut NixFile) -> mx::Result<bool> {
+        self.check_syntax(nix_file.get_file_content()?)?;
+        let SettingsPosition::ExistingOption(option) = self.get_position(nix_file)? else {
+            return Ok(false);
+        };
+        let content = nix_file.get_mut_file_content()?;
+        let def_start = option.get_range_option().start;
+        let line_start = content[..def_start].rfind('\n').map_or(0, |i| i + 1);
+        if content[line_start..def_start].trim().is_empty() {
+            let mut comment_start = line_start;
+            while comment_start > 0 {
+                let previous = content[..comment_start - 1]
+                    .rfind('\n')
+                    .map_or(0, |i| i + 1);
+                if !content[previous..comment_start]
+                    .trim_start()
+                    .starts_with('#')
+                {
+                    break;
+                }
+                comment_start = previous;
+            }
+            content.drain(comment_start..line_start);
+        }
+        self.set_option_to_default(nix_file)
+    }
+
     pub fn set_option_all_instance_to_default(&self, nix_file: &mut NixFile) -> mx::Result<bool> {
         let mut found = false;
         while self.set_option_to_default(nix_file)? {
@@ -154,4 +1085,122 @@ impl<'a> Option<'a> {
         }
         Ok(found)
     }
+
+    /// Removes every definition of the option, along with the flattened
+    /// definitions below it like `a.b.c = 1;` for the option `a.b`.
+    pub fn set_option_tree_to_default_in_str(&self, content: &mut String) -> mx::Result<bool> {
+        let mut found = false;
+        while self.set_option_to_default_in_str(content)? {
+            found = true;
+        }
+        let prefix = format!("{}.", self.nix_option);
+        while let Some((leaf, _)) = SettingsPosition::list_all(&rnix::Root::parse(content).syntax())
+            .into_iter()
+            .find(|(path, _)| path.starts_with(&prefix))
+        {
+            if !Option::new(&leaf).set_option_to_default_in_str(content)? {
+                break;
+            }
+            found = true;
+        }
+        Ok(found)
+    }
+}
+
+/// Writes several options sharing a base path as one block, like
+/// `services.nginx = { enable = true; port = 80; };`, in a single insertion.
+#[allow(dead_code)]
+pub struct OptionBuilder<'a> {
+    base: &'a str,
+    leaves: Vec<(&'a str, &'a str)>,
+    indent_style: std::option::Option<IndentStyle>,
+}
+
+#[allow(dead_code)]
+impl<'a> OptionBuilder<'a> {
+    pub fn new(base: &'a str) -> Self {
+        OptionBuilder {
+            base,
+            leaves: Vec::new(),
+            indent_style: None,
+        }
+    }
+
+    /// Adds `leaf`, relative to the base path, with its raw Nix value.
+    pub fn leaf(mut self, leaf: &'a str, value: &'a str) -> Self {
+        self.leaves.push((leaf, value));
+        self
+    }
+
+    pub fn with_indent_style(mut self, indent_style: IndentStyle) -> Self {
+        self.indent_style = Some(indent_style);
+        self
+    }
+
+    fn base_option(&self) -> Option<'a> {
+        let option = Option::new(self.base);
+        match self.indent_style {
+            Some(style) => option.with_indent_style(style),
+            None => option,
+        }
+    }
+
+    /// Returns the byte range of the written block, or of the base set when
+    /// it already exists and the leaves are set in it one by one.
+    pub fn build(&self, nix_file: &mut NixFile) -> mx::Result<Range<usize>> {
+        self.build_in_str(nix_file.get_mut_file_content()?)
+    }
+
+    /// Same as [`OptionBuilder::build`] on an in-memory content.
+    pub fn build_in_str(&self, content: &mut String) -> mx::Result<Range<usize>> {
+        let option = self.base_option();
+        let pos_insert = match option.get_position_in_str(content)? {
+            SettingsPosition::NewInsertion(pos_insert) => pos_insert,
+            SettingsPosition::ExistingOption(_) => {
+                let mut edited = content.clone();
+                for (leaf, value) in &self.leaves {
+                    let path = format!("{}.{}", self.base, leaf);
+                    let leaf_option = Option {
+                        nix_option: &path,
+                        indent_style: self.indent_style,
+                        force: false,
+                        blank_line_before: false,
+                    };
+                    leaf_option.set_in_str(&mut edited, value)?;
+                }
+                *content = edited;
+                let SettingsPosition::ExistingOption(base) = option.get_position_in_str(content)?
+                else {
+                    return Err(mx::ErrorKind::OptionNotFound);
+                };
+                return Ok(base.get_range_option_value().clone());
+            }
+        };
+
+        if self.leaves.is_empty() {
+            return option.set_in_str(content, "{ }");
+        }
+        // Level of the line holding the innermost key, the leaves being one
+        // level deeper.
+        let level = pos_insert.get_indent_level().max(1)
+            + split_option_path(pos_insert.get_remaining_path()).len()
+            - 1;
+        let style = option.indent_style_for(content);
+        let mut block = String::from("{\n");
+        for (leaf, value) in &self.leaves {
+            block.push_str(&format!(
+                "{}{} = {};\n",
+                style.repeat(level + 1),
+                leaf,
+                value
+            ));
+        }
+        block.push_str(&style.repeat(level));
+        block.push('}');
+        option.set_in_str(content, &block)
+    }
 }
+
+#[cfg(test)]
+#[path = "option_tests.rs"]
+mod tests;
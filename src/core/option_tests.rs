@@ -0,0 +1,1299 @@
+/// Tests for [`Option`].
+///
+/// # Structure
+/// - `unit`        – Edits applied to in-memory content, with no file access.
+/// - `integration` – Edits applied to a `configuration.nix` opened through a
+///   real [`Transaction`] on a temporary Git repository.
+use super::{InsertPosition, Option, OptionBuilder, OptionChange, OptionInfo, OptionPresence};
+use crate::core::IndentStyle;
+use crate::core::transaction::file_lock::NixFile;
+use crate::core::transaction::{Transaction, transaction::BuildCommand};
+use crate::mx;
+use std::fs;
+use tempfile::TempDir;
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Helpers
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Stages and commits every file currently in the working tree.
+fn commit_all(repo: &git2::Repository) {
+    let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+}
+
+/// Opens `content` as `configuration.nix` inside a transaction, applies `f`
+/// and returns the resulting in-memory content. The transaction is rolled
+/// back so nothing is written to disk.
+fn edit<F>(content: &str, f: F) -> mx::Result<String>
+where
+    F: FnOnce(&mut NixFile) -> mx::Result<()>,
+{
+    let dir = TempDir::new().expect("failed to create temporary directory");
+    let repo = git2::Repository::init(dir.path()).expect("git init failed");
+    fs::write(dir.path().join("configuration.nix"), content)
+        .expect("failed to write configuration.nix");
+    commit_all(&repo);
+
+    let repo_path = format!("{}/", dir.path().to_str().unwrap());
+    let mut transaction = Transaction::new(&repo_path, "test", BuildCommand::Install)?;
+    transaction.begin()?;
+    let file = transaction.get_file("configuration.nix")?;
+    let result = f(file).and_then(|_| Ok(file.get_file_content()?.clone()));
+    transaction.rollback()?;
+    result
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Unit tests – in-memory content only
+// ─────────────────────────────────────────────────────────────────────────────
+mod unit {
+    use super::*;
+
+    /// `set_in_str` inserts then replaces a value in a plain string.
+    #[test]
+    fn set_in_str_insert_and_replace() {
+        let mut content = String::from("{\n}\n");
+        let option = Option::new("a.b");
+        option.set_in_str(&mut content, "1").unwrap();
+        assert_eq!(content, "{\n  a = {\n    b = 1;\n  };\n}\n");
+        option.set_in_str(&mut content, "2").unwrap();
+        assert_eq!(option.get_in_str(&content).unwrap(), "2");
+    }
+
+    /// The count stops at the previous newline, and stays fast deep in a
+    /// large file.
+    #[test]
+    fn count_char_before_newline_large_file() {
+        let mut text = "a = 1;\n".repeat(15_000);
+        text.push_str("  é = 1;");
+        assert!(text.len() > 100_000);
+
+        let start = std::time::Instant::now();
+        for _ in 0..1_000 {
+            assert_eq!(
+                Option::count_char_before_newline(&text, text.len()),
+                "  é = 1;".len()
+            );
+        }
+        assert_eq!(Option::count_char_before_newline("abc", 2), 2);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    /// Multibyte characters in values and comments do not shift the ranges.
+    #[test]
+    fn multibyte_values_and_comments() {
+        let mut content =
+            String::from("{\n  # café ☕ déjà\n  a = \"héllo wörld\";\n  # ünïcödé\n  b = 1;\n}\n");
+        assert_eq!(
+            Option::new("a").get_in_str(&content).unwrap(),
+            "\"héllo wörld\""
+        );
+        assert_eq!(Option::new("b").get_in_str(&content).unwrap(), "1");
+
+        let range = Option::new("a")
+            .set_in_str(&mut content, "\"ça va\"")
+            .unwrap();
+        assert_eq!(&content[range], "\"ça va\"");
+        Option::new("c").set_in_str(&mut content, "\"€\"").unwrap();
+        assert!(
+            Option::new("b")
+                .set_option_to_default_in_str(&mut content)
+                .unwrap()
+        );
+        assert_eq!(
+            content,
+            "{\n  # café ☕ déjà\n  a = \"ça va\";\n  # ünïcödé\n  c = \"€\";\n}\n"
+        );
+    }
+
+    /// An inherited option is explicitly set but its value can not be replaced.
+    #[test]
+    fn inherited_option_is_not_replaced() {
+        let mut content = String::from("{\n  inherit enable port;\n}\n");
+        let option = Option::new("port");
+        assert!(matches!(
+            option.set_in_str(&mut content, "80"),
+            Err(mx::ErrorKind::InvalidArgument(_))
+        ));
+        assert_eq!(content, "{\n  inherit enable port;\n}\n");
+    }
+
+    /// Each insert position places the new option as requested.
+    #[test]
+    fn set_ordered_positions() {
+        let set = |option: &str, position| {
+            let mut content = String::from("{\n  a = 1;\n  c = 3;\n}\n");
+            let range = Option::new(option)
+                .set_ordered_in_str(&mut content, "2", position)
+                .unwrap();
+            assert_eq!(&content[range], "2");
+            content
+        };
+        let between = "{\n  a = 1;\n  b = 2;\n  c = 3;\n}\n";
+        let last = "{\n  a = 1;\n  c = 3;\n  b = 2;\n}\n";
+        assert_eq!(set("b", InsertPosition::End), last);
+        assert_eq!(set("b", InsertPosition::Alphabetical), between);
+        assert_eq!(
+            set("d", InsertPosition::Alphabetical),
+            "{\n  a = 1;\n  c = 3;\n  d = 2;\n}\n"
+        );
+        assert_eq!(set("b", InsertPosition::AfterKey("a")), between);
+        assert_eq!(set("b", InsertPosition::AfterKey("c")), last);
+        assert_eq!(set("b", InsertPosition::AfterKey("missing")), last);
+        assert_eq!(
+            set("b.x", InsertPosition::Alphabetical),
+            "{\n  a = 1;\n  b = {\n    x = 2;\n  };\n  c = 3;\n}\n"
+        );
+    }
+
+    /// Ordering applies inside nested sets too.
+    #[test]
+    fn set_ordered_nested_set() {
+        let mut content = String::from("{\n  s = {\n    a = 1;\n    c = 3;\n  };\n}\n");
+        Option::new("s.b")
+            .set_ordered_in_str(&mut content, "2", InsertPosition::Alphabetical)
+            .unwrap();
+        assert_eq!(
+            content,
+            "{\n  s = {\n    a = 1;\n    b = 2;\n    c = 3;\n  };\n}\n"
+        );
+    }
+
+    /// The direct definitions of a set are returned as key/value text pairs.
+    #[test]
+    fn get_attrset_pairs() {
+        let content = "{\n  services.openssh = {\n    enable = true;\n    settings.PermitRootLogin = \"no\";\n    ports = [ 22 ];\n    extra = { a = 1; };\n  };\n}\n";
+        assert_eq!(
+            Option::new("services.openssh")
+                .get_attrset_in_str(content)
+                .unwrap(),
+            [
+                ("enable".to_string(), "true".to_string()),
+                ("settings.PermitRootLogin".to_string(), "\"no\"".to_string()),
+                ("ports".to_string(), "[ 22 ]".to_string()),
+                ("extra".to_string(), "{ a = 1; }".to_string()),
+            ]
+        );
+        assert!(matches!(
+            Option::new("services.openssh.enable").get_attrset_in_str(content),
+            Err(mx::ErrorKind::OptionIsNotAttrSet)
+        ));
+    }
+
+    // ── typed getters ─────────────────────────────────────────────────────────
+
+    const NUMBERS: &str = "{\n  int = 42;\n  neg = -1;\n  spaced = - 7;\n  float = 2.75;\n  sci = 1.5e3;\n  negf = -0.5;\n  str = \"12\";\n  ident = inf;\n}\n";
+
+    /// Integers, including negative ones, are read as `i64`.
+    #[test]
+    fn get_as_i64_forms() {
+        let get = |option| Option::new(option).get_as_i64_in_str(NUMBERS);
+        assert_eq!(get("int").unwrap(), 42);
+        assert_eq!(get("neg").unwrap(), -1);
+        assert_eq!(get("spaced").unwrap(), -7);
+        for not_int in ["float", "str", "ident"] {
+            assert!(matches!(
+                get(not_int),
+                Err(mx::ErrorKind::OptionTypeMismatch(_))
+            ));
+        }
+        assert!(matches!(get("missing"), Err(mx::ErrorKind::OptionNotFound)));
+    }
+
+    /// Floats, scientific notation and integers are read as `f64`.
+    #[test]
+    fn get_as_f64_forms() {
+        let get = |option| Option::new(option).get_as_f64_in_str(NUMBERS);
+        assert_eq!(get("float").unwrap(), 2.75);
+        assert_eq!(get("sci").unwrap(), 1500.0);
+        assert_eq!(get("negf").unwrap(), -0.5);
+        assert_eq!(get("int").unwrap(), 42.0);
+        for not_number in ["str", "ident"] {
+            assert!(matches!(
+                get(not_number),
+                Err(mx::ErrorKind::OptionTypeMismatch(_))
+            ));
+        }
+    }
+
+    /// Only the `true` and `false` identifiers are booleans.
+    #[test]
+    fn get_as_bool_forms() {
+        let content = "{\n  yes = true;\n  no = false;\n  str = \"true\";\n  nothing = null;\n}\n";
+        let get = |option| Option::new(option).get_as_bool_in_str(content);
+        assert!(get("yes").unwrap());
+        assert!(!get("no").unwrap());
+        for not_bool in ["str", "nothing"] {
+            assert!(matches!(
+                get(not_bool),
+                Err(mx::ErrorKind::OptionTypeMismatch(_))
+            ));
+        }
+        assert!(matches!(get("missing"), Err(mx::ErrorKind::OptionNotFound)));
+    }
+
+    /// A raw value written with `set_in_str` reads back as its logical string.
+    #[test]
+    fn get_string_round_trips_escapes() {
+        let cases = [
+            (r#""plain""#, "plain"),
+            (r#""say \"hi\"""#, r#"say "hi""#),
+            (r#""a\\b\tc\nd""#, "a\\b\tc\nd"),
+            (r#""\${HOME}""#, "${HOME}"),
+        ];
+        for (raw, expected) in cases {
+            let mut content = String::from("{\n}\n");
+            let option = Option::new("a.b");
+            option.set_in_str(&mut content, raw).unwrap();
+            assert_eq!(option.get_string_in_str(&content).unwrap(), expected);
+        }
+    }
+
+    /// `set_string_in_str` escapes what `get_string_in_str` unescapes.
+    #[test]
+    fn set_string_round_trips() {
+        let raw = r#"say "hi" to ${USER} \o/ $HOME"#;
+        let mut content = String::from("{\n}\n");
+        let option = Option::new("a.b");
+        option.set_string_in_str(&mut content, raw).unwrap();
+        assert!(rnix::Root::parse(&content).errors().is_empty());
+        assert_eq!(option.get_string_in_str(&content).unwrap(), raw);
+    }
+
+    /// `''` strings are dedented and their own escapes resolved.
+    #[test]
+    fn get_string_indented() {
+        let content = "{\n  s = ''\n    line one\n      nested ''${x} '''q'''\n  '';\n}\n";
+        assert_eq!(
+            Option::new("s").get_string_in_str(content).unwrap(),
+            "line one\n  nested ${x} ''q''\n"
+        );
+    }
+
+    /// Anything that is not a string literal is a type mismatch.
+    #[test]
+    fn get_string_not_a_string() {
+        let content = "{\n  n = 1;\n  l = [ \"a\" ];\n  c = \"a\" + \"b\";\n}\n";
+        for option in ["n", "l", "c"] {
+            assert!(matches!(
+                Option::new(option).get_string_in_str(content),
+                Err(mx::ErrorKind::OptionTypeMismatch(_))
+            ));
+        }
+    }
+
+    /// A quoted key containing a dot is written as one attribute, then found
+    /// again by the same path.
+    #[test]
+    fn set_string_literal_key() {
+        let mut content = String::from("{\n}\n");
+        let option = Option::new("virtualHosts.\"example.com\".root");
+        option.set_in_str(&mut content, "\"/srv\"").unwrap();
+        assert_eq!(
+            content,
+            "{\n  virtualHosts = {\n    \"example.com\" = {\n      root = \"/srv\";\n    };\n  };\n}\n"
+        );
+        option.set_in_str(&mut content, "\"/var\"").unwrap();
+        assert_eq!(option.get_in_str(&content).unwrap(), "\"/var\"");
+    }
+
+    /// Absolute, relative and home paths are written as is, anything else is
+    /// refused without touching the content.
+    #[test]
+    fn set_path_validates_value() {
+        let option = Option::new("fileSystems.\"/\".device");
+        for path in ["/dev/sda1", "./disk.img", "~/disk.img"] {
+            let mut content = String::from("{\n}\n");
+            option.set_path_in_str(&mut content, path).unwrap();
+            assert_eq!(option.get_in_str(&content).unwrap(), path);
+        }
+
+        let mut content = String::from("{\n}\n");
+        assert!(matches!(
+            option.set_path_in_str(&mut content, "/dev/ with spaces"),
+            Err(mx::ErrorKind::InvalidArgument(_))
+        ));
+        assert_eq!(content, "{\n}\n");
+    }
+
+    // ── value compatibility ───────────────────────────────────────────────────
+
+    /// Replacing a value by one of another literal kind is reported, an
+    /// absent option or a non-literal value is always compatible.
+    #[test]
+    fn check_value_compatible_kinds() {
+        let content = "{\n  flag = true;\n  count = 1;\n  pkg = pkgs.hello;\n}\n";
+        let check = |option, value| {
+            Option::new(option)
+                .check_value_compatible_in_str(content, value)
+                .unwrap()
+        };
+        assert!(!check("flag", "\"yes\""));
+        assert!(check("flag", "false"));
+        assert!(check("count", "42"));
+        assert!(!check("count", "[ 1 ]"));
+        assert!(check("count", "lib.mkDefault 2"));
+        assert!(check("pkg", "\"hello\""));
+        assert!(check("missing", "\"anything\""));
+    }
+
+    // ── JSON export ───────────────────────────────────────────────────────────
+
+    /// Dotted and nested definitions are merged into nested objects, lists
+    /// become arrays, and quoted keys are kept whole.
+    #[test]
+    fn export_json_small_config() {
+        let content = r#"{ config, pkgs, ... }:
+{
+  networking.hostName = "host";
+  services.nginx = {
+    enable = true;
+    virtualHosts."example.com".root = "/srv";
+  };
+  environment.systemPackages = with pkgs; [ vim git ];
+}
+"#;
+        assert_eq!(
+            Option::export_json_in_str(content).unwrap(),
+            r#"{"environment":{"systemPackages":["vim","git"]},"networking":{"hostName":"\"host\""},"services":{"nginx":{"enable":"true","virtualHosts":{"example.com":{"root":"\"/srv\""}}}}}"#
+        );
+    }
+
+    /// A file which does not parse is not exported.
+    #[test]
+    fn export_json_invalid_file() {
+        assert!(matches!(
+            Option::export_json_in_str("{ a = ; }"),
+            Err(mx::ErrorKind::InvalidFile)
+        ));
+    }
+
+    // ── merge ─────────────────────────────────────────────────────────────────
+
+    /// The keys of the new set are added to the existing one, a duplicated
+    /// key taking the new value.
+    #[test]
+    fn merge_attrsets_keeps_existing_keys() {
+        let mut content = String::from(
+            "{\n  services.nginx = {\n    enable = true;\n    port = 8080;\n  };\n}\n",
+        );
+        let option = Option::new("services.nginx");
+        option
+            .merge_in_str(&mut content, "{ port = 80; user = \"www\"; }")
+            .unwrap();
+        assert_eq!(
+            content,
+            "{\n  services.nginx = {\n    enable = true;\n    port = 80;\n    user = \"www\";\n  };\n}\n"
+        );
+    }
+
+    /// A new set with an `inherit` is refused instead of replacing the
+    /// existing one.
+    #[test]
+    fn merge_with_inherit_is_refused() {
+        let original = "{\n  services.nginx = {\n    enable = true;\n  };\n}\n";
+        let mut content = String::from(original);
+        assert!(matches!(
+            Option::new("services.nginx").merge_in_str(&mut content, "{ inherit foo; port = 80; }"),
+            Err(mx::ErrorKind::InvalidArgument(_))
+        ));
+        assert_eq!(content, original);
+    }
+
+    /// A value that is not an attribute set, on either side, is replaced.
+    #[test]
+    fn merge_non_attrset_replaces() {
+        let mut content = String::from("{\n  a = 1;\n  b = { c = 1; };\n}\n");
+        Option::new("a")
+            .merge_in_str(&mut content, "{ d = 2; }")
+            .unwrap();
+        Option::new("b").merge_in_str(&mut content, "3").unwrap();
+        assert_eq!(content, "{\n  a = { d = 2; };\n  b = 3;\n}\n");
+    }
+
+    // ── OptionBuilder ─────────────────────────────────────────────────────────
+
+    /// A two-leaf block is written in one insertion under the base path.
+    #[test]
+    fn builder_two_leaves() {
+        let mut content = String::from("{\n}\n");
+        OptionBuilder::new("services.nginx")
+            .leaf("enable", "true")
+            .leaf("port", "80")
+            .build_in_str(&mut content)
+            .unwrap();
+        assert!(rnix::Root::parse(&content).errors().is_empty());
+        assert_eq!(
+            content,
+            "{\n  services = {\n    nginx = {\n      enable = true;\n      port = 80;\n    };\n  };\n}\n"
+        );
+    }
+
+    /// Leaves may be dotted, and a partially defined base path is completed.
+    #[test]
+    fn builder_three_leaves() {
+        let mut content = String::from("{\n  services = {\n  };\n}\n");
+        OptionBuilder::new("services.nginx")
+            .leaf("enable", "true")
+            .leaf("virtualHosts.default.root", "\"/srv\"")
+            .leaf("recommendedGzipSettings", "true")
+            .build_in_str(&mut content)
+            .unwrap();
+        assert!(rnix::Root::parse(&content).errors().is_empty());
+        for (path, value) in [
+            ("services.nginx.enable", "true"),
+            ("services.nginx.virtualHosts.default.root", "\"/srv\""),
+            ("services.nginx.recommendedGzipSettings", "true"),
+        ] {
+            assert_eq!(Option::new(path).get_in_str(&content).unwrap(), value);
+        }
+        assert!(content.contains("    nginx = {\n      enable = true;\n"));
+    }
+
+    /// Leaves of an existing base set are set in it.
+    #[test]
+    fn builder_existing_base() {
+        let mut content = String::from("{\n  services.nginx = {\n    enable = false;\n  };\n}\n");
+        OptionBuilder::new("services.nginx")
+            .leaf("enable", "true")
+            .leaf("port", "80")
+            .build_in_str(&mut content)
+            .unwrap();
+        assert_eq!(
+            content,
+            "{\n  services.nginx = {\n    enable = true;\n    port = 80;\n  };\n}\n"
+        );
+    }
+
+    // ── blank line before ─────────────────────────────────────────────────────
+
+    /// The first option of an empty set gets no blank line, the next ones do.
+    #[test]
+    fn blank_line_before_inserted_options() {
+        let mut content = String::from("{\n}\n");
+        Option::new("a")
+            .with_blank_line_before(true)
+            .set_in_str(&mut content, "1")
+            .unwrap();
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+        let range = Option::new("services.x.enable")
+            .with_blank_line_before(true)
+            .set_in_str(&mut content, "true")
+            .unwrap();
+        assert_eq!(
+            content,
+            "{\n  a = 1;\n\n  services = {\n    x = {\n      enable = true;\n    };\n  };\n}\n"
+        );
+        assert_eq!(&content[range], "true");
+    }
+
+    /// Without the flag, or in a set still empty, options are packed.
+    #[test]
+    fn blank_line_before_disabled_or_empty_set() {
+        let mut content = String::from("{\n  a = 1;\n  s = {\n  };\n}\n");
+        Option::new("b").set_in_str(&mut content, "2").unwrap();
+        Option::new("s.c")
+            .with_blank_line_before(true)
+            .set_in_str(&mut content, "3")
+            .unwrap();
+        assert_eq!(
+            content,
+            "{\n  a = 1;\n  s = {\n    c = 3;\n  };\n  b = 2;\n}\n"
+        );
+    }
+
+    // ── diff_options ──────────────────────────────────────────────────────────
+
+    /// A changed, an added and a removed option are reported, while moving
+    /// an option into a nested set or reformatting a list is not.
+    #[test]
+    fn diff_options_changes() {
+        let old = "{\n  services.nginx.enable = true;\n  networking.hostName = \"nixos\";\n  boot.loader.timeout = 5;\n  environment.systemPackages = [ vim git ];\n}\n";
+        let new = "{\n  services.nginx = {\n    enable = false;\n  };\n  networking.hostName = \"nixos\"; # host\n  time.timeZone = \"Europe/Paris\";\n  environment.systemPackages = [\n    vim\n    git\n  ];\n}\n";
+        assert_eq!(
+            Option::diff_options(old, new).unwrap(),
+            vec![
+                OptionChange::Removed {
+                    path: "boot.loader.timeout".to_string(),
+                    old: "5".to_string(),
+                },
+                OptionChange::Changed {
+                    path: "services.nginx.enable".to_string(),
+                    old: "true".to_string(),
+                    new: "false".to_string(),
+                },
+                OptionChange::Added {
+                    path: "time.timeZone".to_string(),
+                    value: "\"Europe/Paris\"".to_string(),
+                },
+            ]
+        );
+        assert_eq!(Option::diff_options(old, old).unwrap(), vec![]);
+    }
+
+    // ── presence ──────────────────────────────────────────────────────────────
+
+    /// A defined leaf, a path whose parent set only is defined, and a path
+    /// missing entirely.
+    #[test]
+    fn presence_per_definition() {
+        let content = "{\n  services.nginx = {\n    enable = true;\n  };\n}\n";
+        let presence = |path| Option::new(path).presence_in_str(content).unwrap();
+        assert_eq!(
+            presence("services.nginx.enable"),
+            OptionPresence::FullyDefined
+        );
+        assert_eq!(
+            presence("services.nginx.virtualHosts.default.root"),
+            OptionPresence::PrefixExists {
+                missing: "virtualHosts.default.root".to_string()
+            }
+        );
+        assert_eq!(presence("boot.loader.timeout"), OptionPresence::Absent);
+    }
+
+    // ── get_with_context ──────────────────────────────────────────────────────
+
+    /// Value, ranges and indent level of an option nested two levels deep.
+    #[test]
+    fn get_with_context_nested() {
+        let content = "{\n  services = {\n    nginx = {\n      enable = true;\n    };\n  };\n}\n";
+        let info = Option::new("services.nginx.enable")
+            .get_with_context_in_str(content)
+            .unwrap()
+            .unwrap();
+        let value_start = content.find("true").unwrap();
+        let def_start = content.find("enable").unwrap();
+        assert_eq!(
+            info,
+            OptionInfo {
+                value: "true".to_string(),
+                value_range: value_start..value_start + 4,
+                def_range: def_start..value_start + "true;".len(),
+                indent_level: 3,
+            }
+        );
+        assert_eq!(
+            Option::new("services.nginx.package")
+                .get_with_context_in_str(content)
+                .unwrap(),
+            None
+        );
+    }
+
+    /// Inserting into an empty set gives one option per line.
+    #[test]
+    fn set_in_empty_set() {
+        for empty in ["{}\n", "{ }\n"] {
+            let mut content = String::from(empty);
+            Option::new("a").set_in_str(&mut content, "1").unwrap();
+            assert_eq!(content, "{\n  a = 1;\n}\n");
+        }
+        for empty in ["{\n  x = {};\n}\n", "{\n  x = { };\n}\n"] {
+            let mut content = String::from(empty);
+            Option::new("x.a").set_in_str(&mut content, "1").unwrap();
+            assert_eq!(content, "{\n  x = {\n    a = 1;\n  };\n}\n");
+        }
+    }
+
+    /// `set_option_to_default_in_str` removes the definition.
+    #[test]
+    fn set_option_to_default_in_str_removes() {
+        let mut content = String::from("{\n  a = 1;\n  b = 2;\n}\n");
+        assert!(
+            Option::new("b")
+                .set_option_to_default_in_str(&mut content)
+                .unwrap()
+        );
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+        assert!(
+            !Option::new("b")
+                .set_option_to_default_in_str(&mut content)
+                .unwrap()
+        );
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Integration tests
+// ─────────────────────────────────────────────────────────────────────────────
+mod integration {
+    use super::*;
+
+    // ── indent style ──────────────────────────────────────────────────────────
+
+    /// The default style keeps the historical 2-space indentation.
+    #[test]
+    fn set_nested_with_default_indent() {
+        let content = edit("{\n}\n", |file| {
+            Option::new("services.nginx.enable").set(file, "true")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  services = {\n    nginx = {\n      enable = true;\n    };\n  };\n}\n"
+        );
+    }
+
+    /// A new nested option is written with 4-space units.
+    #[test]
+    fn set_nested_with_four_spaces() {
+        let content = edit("{\n}\n", |file| {
+            Option::new("services.nginx.enable")
+                .with_indent_style(IndentStyle::Spaces(4))
+                .set(file, "true")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n    services = {\n        nginx = {\n            enable = true;\n        };\n    };\n}\n"
+        );
+    }
+
+    /// A new nested option is written with tabs.
+    #[test]
+    fn set_nested_with_tabs() {
+        let content = edit("{\n}\n", |file| {
+            Option::new("services.nginx.enable")
+                .with_indent_style(IndentStyle::Tabs)
+                .set(file, "true")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n\tservices = {\n\t\tnginx = {\n\t\t\tenable = true;\n\t\t};\n\t};\n}\n"
+        );
+    }
+
+    /// Without an explicit style, a tab-indented file keeps using tabs.
+    #[test]
+    fn set_detects_tabs() {
+        let content = edit("{\n\ta = 1;\n}\n", |file| {
+            Option::new("b.c").set(file, "2")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n\ta = 1;\n\tb = {\n\t\tc = 2;\n\t};\n}\n");
+    }
+
+    /// Without an explicit style, a 4-space file keeps using 4 spaces.
+    #[test]
+    fn set_detects_four_spaces() {
+        let content = edit("{\n    a = {\n        x = 1;\n    };\n}\n", |file| {
+            Option::new("b.c").set(file, "2")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n    a = {\n        x = 1;\n    };\n    b = {\n        c = 2;\n    };\n}\n"
+        );
+    }
+
+    // ── set_option_to_default ─────────────────────────────────────────────────
+
+    /// Removing the first option of the set must not underflow nor leave
+    /// a dangling indentation behind.
+    #[test]
+    fn set_option_to_default_first_option() {
+        let content = edit("{a = 1; b = 2;}", |file| {
+            assert!(Option::new("a").set_option_to_default(file)?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{ b = 2;}");
+    }
+
+    /// The whitespace preceding the removed definition is removed with it.
+    #[test]
+    fn set_option_to_default_trims_preceding_whitespace() {
+        let content = edit("{\n  a = 1;\n  b = 2;\n}\n", |file| {
+            assert!(Option::new("b").set_option_to_default(file)?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+    }
+
+    /// The comment lines above the option are removed with it, an earlier
+    /// comment separated by a blank line is kept.
+    #[test]
+    fn set_option_to_default_with_comments() {
+        let content = edit(
+            "{\n  # unrelated\n  a = 1;\n\n  # explains b\n  # more\n  b = 2;\n}\n",
+            |file| {
+                assert!(Option::new("b").set_option_to_default_with_comments(file)?);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(content, "{\n  # unrelated\n  a = 1;\n}\n");
+    }
+
+    /// The comment run stops at the previous option.
+    #[test]
+    fn set_option_to_default_with_comments_stops_at_option() {
+        let content = edit(
+            "{\n  a = 1;\n  # about b\n  b = 2;\n  c = 3;\n}\n",
+            |file| {
+                assert!(Option::new("b").set_option_to_default_with_comments(file)?);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(content, "{\n  a = 1;\n  c = 3;\n}\n");
+    }
+
+    /// Removing an absent option leaves the file untouched.
+    #[test]
+    fn set_option_to_default_absent() {
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            assert!(!Option::new("b").set_option_to_default(file)?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+    }
+
+    // ── errors ────────────────────────────────────────────────────────────────
+
+    /// Reading a missing option reports `OptionNotFound`.
+    #[test]
+    fn get_missing_option_is_option_not_found() {
+        let result = edit("{\n  a = 1;\n}\n", |file| {
+            Option::new("b").get(file)?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(mx::ErrorKind::OptionNotFound)));
+
+        let result = edit("{\n  a = 1;\n}\n", |file| {
+            Option::get_option(file, "b.c")?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(mx::ErrorKind::OptionNotFound)));
+    }
+
+    /// `try_get` returns the value of a defined option.
+    #[test]
+    fn try_get_found() {
+        edit("{\n  a.b = 1;\n}\n", |file| {
+            assert_eq!(Option::new("a.b").try_get(file)?, Some("1"));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// `try_get` returns `None` for an undefined option.
+    #[test]
+    fn try_get_absent() {
+        edit("{\n  a.b = 1;\n}\n", |file| {
+            assert_eq!(Option::new("a.c").try_get(file)?, None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// `try_get` still fails on a file without any attribute set.
+    #[test]
+    fn try_get_unparseable_file() {
+        edit("not ( nix\n", |file| {
+            assert!(matches!(
+                Option::new("a").try_get(file),
+                Err(mx::ErrorKind::InvalidFile)
+            ));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// A defined option is explicitly set, a partially or fully absent one is not.
+    #[test]
+    fn is_explicitly_set() {
+        edit(
+            "{\n  services.nginx = {\n    enable = true;\n  };\n}\n",
+            |file| {
+                assert!(Option::new("services.nginx.enable").is_explicitly_set(file)?);
+                assert!(!Option::new("services.nginx.package").is_explicitly_set(file)?);
+                assert!(!Option::new("boot.loader.timeout").is_explicitly_set(file)?);
+                Ok(())
+            },
+        )
+        .unwrap();
+    }
+
+    // ── set: written range ────────────────────────────────────────────────────
+
+    /// Replacing a value returns the range of the new value.
+    #[test]
+    fn set_returns_range_of_replaced_value() {
+        let mut range = 0..0;
+        let content = edit("{\n  a.b = 1;\n  c = 2;\n}\n", |file| {
+            range = Option::new("a.b").set(file, "\"hello\"")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(&content[range], "\"hello\"");
+    }
+
+    /// Inserting a nested option returns the range of the value inside the
+    /// synthesized block.
+    #[test]
+    fn set_returns_range_of_inserted_value() {
+        let mut range = 0..0;
+        let content = edit("{\n  x = 1;\n}\n", |file| {
+            range = Option::new("services.nginx.enable").set(file, "true")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(&content[range.clone()], "true");
+        assert!(content[..range.start].ends_with("enable = "));
+    }
+
+    // ── preview_set ───────────────────────────────────────────────────────────
+
+    /// The preview of an insertion shows the added line, and the file is untouched.
+    #[test]
+    fn preview_set_inserted_option() {
+        let original = "{\n  a = 1;\n}\n";
+        let mut diff = String::new();
+        let content = edit(original, |file| {
+            diff = Option::new("b").preview_set(file, "2")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, original);
+        assert!(diff.contains("\n+  b = 2;\n"));
+        assert!(!diff.contains("\n-"));
+    }
+
+    /// The preview of a replacement shows the old and the new line.
+    #[test]
+    fn preview_set_replaced_value() {
+        let mut diff = String::new();
+        edit("{\n  a = 1;\n}\n", |file| {
+            diff = Option::new("a").preview_set(file, "2")?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(diff.contains("\n-  a = 1;\n+  a = 2;\n"));
+    }
+
+    // ── set_many ──────────────────────────────────────────────────────────────
+
+    /// Options are applied in order, later ones seeing the earlier edits.
+    #[test]
+    fn set_many_applies_in_order() {
+        let content = edit("{\n}\n", |file| {
+            Option::set_many(
+                file,
+                &[
+                    ("time.timeZone", "\"Europe/Paris\""),
+                    ("i18n.defaultLocale", "\"fr_FR.UTF-8\""),
+                    ("time.timeZone", "\"UTC\""),
+                ],
+            )
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  time = {\n    timeZone = \"UTC\";\n  };\n  i18n = {\n    defaultLocale = \"fr_FR.UTF-8\";\n  };\n}\n"
+        );
+    }
+
+    /// Every entry of the map is written, in key order, and the result parses.
+    #[test]
+    fn set_from_map_applies_all_entries() {
+        let options = std::collections::BTreeMap::from([
+            ("time.timeZone".to_string(), "\"UTC\"".to_string()),
+            ("networking.hostName".to_string(), "\"host\"".to_string()),
+            ("boot.loader.timeout".to_string(), "5".to_string()),
+        ]);
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            Option::set_from_map(file, &options)
+        })
+        .unwrap();
+        assert!(rnix::Root::parse(&content).errors().is_empty());
+        assert_eq!(
+            content,
+            "{\n  a = 1;\n  boot = {\n    loader = {\n      timeout = 5;\n    };\n  };\n  networking = {\n    hostName = \"host\";\n  };\n  time = {\n    timeZone = \"UTC\";\n  };\n}\n"
+        );
+    }
+
+    // ── set_if_absent ─────────────────────────────────────────────────────────
+
+    /// An absent option is written.
+    #[test]
+    fn set_if_absent_writes_absent_option() {
+        let mut written = false;
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            written = Option::new("b").set_if_absent(file, "2")?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(written);
+        assert_eq!(content, "{\n  a = 1;\n  b = 2;\n}\n");
+    }
+
+    /// An existing value is left alone.
+    #[test]
+    fn set_if_absent_skips_present_option() {
+        let mut written = true;
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            written = Option::new("a").set_if_absent(file, "2")?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(!written);
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+    }
+
+    // ── rename ────────────────────────────────────────────────────────────────
+
+    /// A flat key is renamed with its value.
+    #[test]
+    fn rename_flat_key() {
+        let mut renamed = false;
+        let content = edit("{\n  a = 1;\n  b = 2;\n}\n", |file| {
+            renamed = Option::rename(file, "a", "c")?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(renamed);
+        assert_eq!(content, "{\n  b = 2;\n  c = 1;\n}\n");
+    }
+
+    /// A nested dotted path is moved, its value text kept verbatim.
+    #[test]
+    fn rename_nested_path() {
+        let mut renamed = false;
+        let content = edit(
+            "{\n  services.openssh = {\n    enable = true;\n    permitRootLogin = \"no\";\n  };\n}\n",
+            |file| {
+                renamed = Option::rename(
+                    file,
+                    "services.openssh.permitRootLogin",
+                    "services.openssh.settings.PermitRootLogin",
+                )?;
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(renamed);
+        assert_eq!(
+            content,
+            "{\n  services.openssh = {\n    enable = true;\n    settings = {\n      PermitRootLogin = \"no\";\n    };\n  };\n}\n"
+        );
+    }
+
+    /// Renaming an absent option does nothing.
+    #[test]
+    fn rename_absent_option() {
+        let mut renamed = true;
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            renamed = Option::rename(file, "b", "c")?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(!renamed);
+        assert_eq!(content, "{\n  a = 1;\n}\n");
+    }
+
+    // ── rec sets ──────────────────────────────────────────────────────────────
+
+    /// Options inside a `rec` set are found and replaced.
+    #[test]
+    fn set_existing_option_in_rec_set() {
+        let content = edit(
+            "{\n  x = rec {\n    a = 1;\n    b = a;\n  };\n}\n",
+            |file| {
+                assert_eq!(Option::new("x.a").get(file)?, "1");
+                Option::new("x.a").set(file, "2")?;
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(content, "{\n  x = rec {\n    a = 2;\n    b = a;\n  };\n}\n");
+    }
+
+    /// New options are inserted before the closing brace of a `rec` set.
+    #[test]
+    fn set_new_option_in_rec_set() {
+        let content = edit("{\n  x = rec {\n    a = 1;\n  };\n}\n", |file| {
+            Option::new("x.b").set(file, "a")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  x = rec {\n    a = 1;\n    b = a;\n  };\n}\n");
+
+        let content = edit("rec {\n  a = 1;\n}\n", |file| {
+            Option::new("b").set(file, "a")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "rec {\n  a = 1;\n  b = a;\n}\n");
+    }
+
+    // ── multiline strings ─────────────────────────────────────────────────────
+
+    /// A new option gets a `''` block indented one level below the option.
+    #[test]
+    fn set_multiline_new_option() {
+        let content = edit("{\n}\n", |file| {
+            Option::new("environment.etc.motd.text")
+                .set_multiline(file, &["Hello", "", "  world"])?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  environment = {\n    etc = {\n      motd = {\n        text = ''\n          Hello\n\n            world\n        '';\n      };\n    };\n  };\n}\n"
+        );
+    }
+
+    /// An existing value is replaced, aligned on the option line with the
+    /// detected indent unit.
+    #[test]
+    fn set_multiline_existing_option() {
+        let content = edit("{\n    script = \"true\";\n}\n", |file| {
+            Option::new("script").set_multiline(file, &["echo a", "echo b"])?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n    script = ''\n        echo a\n        echo b\n    '';\n}\n"
+        );
+    }
+
+    /// Lines read back are the lines written, blank lines and inner
+    /// indentation included.
+    #[test]
+    fn multiline_round_trip() {
+        let lines = ["[Unit]", "", "Description=test", "  indented", "", "end"];
+        edit("{\n  a.b = 1;\n}\n", |file| {
+            let option = Option::new("a.b");
+            option.set_multiline(file, &lines)?;
+            assert_eq!(option.get_multiline(file)?, lines);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// On a set written on a single line, only the line indentation is
+    /// reused, not the code before the option.
+    #[test]
+    fn set_multiline_on_single_line_set() {
+        let content = edit("{ a = 1; b = \"x\"; }\n", |file| {
+            let option = Option::new("b");
+            option.set_multiline(file, &["x", "y"])?;
+            assert_eq!(option.get_multiline(file)?, ["x", "y"]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{ a = 1; b = ''\n  x\n  y\n''; }\n");
+        assert!(rnix::Root::parse(&content).errors().is_empty());
+    }
+
+    /// In a tab-indented file, the `''` body is indented with spaces, which
+    /// Nix strips, so the value round-trips.
+    #[test]
+    fn set_multiline_in_tab_indented_file() {
+        let content = edit("{\n\ta = 1;\n\tb = \"x\";\n}\n", |file| {
+            let option = Option::new("b");
+            option.set_multiline(file, &["echo a", "  echo b"])?;
+            assert_eq!(option.get_multiline(file)?, ["echo a", "  echo b"]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n\ta = 1;\n\tb = ''\n    echo a\n      echo b\n  '';\n}\n"
+        );
+    }
+
+    /// A value which is not a `''` string is rejected.
+    #[test]
+    fn get_multiline_on_plain_string() {
+        edit("{\n  a = \"x\";\n}\n", |file| {
+            assert!(matches!(
+                Option::new("a").get_multiline(file),
+                Err(mx::ErrorKind::InvalidNixString)
+            ));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // ── single-line sets ──────────────────────────────────────────────────────
+
+    /// An option inserted into a set written on one line goes on its own line.
+    #[test]
+    fn set_in_single_line_set() {
+        let content = edit("{ services.nginx.enable = true; }\n", |file| {
+            Option::new("services.apache.enable").set(file, "true")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{ services.nginx.enable = true;\n  services = {\n    apache = {\n      enable = true;\n    };\n  };\n}\n"
+        );
+        assert!(rnix::Root::parse(&content).errors().is_empty());
+    }
+
+    /// A nested one-line set is split the same way.
+    #[test]
+    fn set_in_nested_single_line_set() {
+        let content = edit("{\n  a = { b = 1; };\n}\n", |file| {
+            let range = Option::new("a.c").set(file, "2")?;
+            assert_eq!(&file.get_file_content()?[range], "2");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = { b = 1;\n    c = 2;\n  };\n}\n");
+    }
+
+    // ── comments ──────────────────────────────────────────────────────────────
+
+    /// The comment is written above the outermost inserted key.
+    #[test]
+    fn set_with_comment_insert() {
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            let range = Option::new("services.nginx.enable").set_with_comment(
+                file,
+                "true",
+                Some("set by modulix"),
+            )?;
+            assert_eq!(&file.get_file_content()?[range], "true");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  a = 1;\n  # set by modulix\n  services = {\n    nginx = {\n      enable = true;\n    };\n  };\n}\n"
+        );
+    }
+
+    /// On replace the comment goes above the existing definition, once.
+    #[test]
+    fn set_with_comment_replace() {
+        let content = edit("{\n  a = {\n    b = 1;\n  };\n}\n", |file| {
+            let option = Option::new("a.b");
+            option.set_with_comment(file, "2", Some("set by modulix"))?;
+            let range = option.set_with_comment(file, "3", Some("set by modulix"))?;
+            assert_eq!(&file.get_file_content()?[range], "3");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  a = {\n    # set by modulix\n    b = 3;\n  };\n}\n"
+        );
+    }
+
+    /// A comment spanning several lines is recognised as already present.
+    #[test]
+    fn set_with_comment_replace_multiline() {
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            let option = Option::new("a");
+            option.set_with_comment(file, "2", Some("set by modulix\ndo not edit"))?;
+            let range = option.set_with_comment(file, "3", Some("set by modulix\ndo not edit"))?;
+            assert_eq!(&file.get_file_content()?[range], "3");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  # set by modulix\n  # do not edit\n  a = 3;\n}\n"
+        );
+    }
+
+    /// Without a comment it behaves like `set`.
+    #[test]
+    fn set_with_comment_none() {
+        let content = edit("{\n  a = 1;\n}\n", |file| {
+            Option::new("a").set_with_comment(file, "2", None)?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = 2;\n}\n");
+    }
+
+    // ── nested insertion ──────────────────────────────────────────────────────
+
+    /// A 3-level remaining path is wrapped in nested sets inside the existing one.
+    #[test]
+    fn set_three_level_remaining_path_in_existing_set() {
+        let content = edit(
+            "{\n  services.nginx = {\n    enable = true;\n  };\n}\n",
+            |file| {
+                let option = Option::new("services.nginx.virtualHosts.\"x\".root");
+                option.set(file, "\"/var/www\"")?;
+                assert_eq!(option.get(file)?, "\"/var/www\"");
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            content,
+            "{\n  services.nginx = {\n    enable = true;\n    virtualHosts = {\n      \"x\" = {\n        root = \"/var/www\";\n      };\n    };\n  };\n}\n"
+        );
+    }
+
+    // ── syntax check ──────────────────────────────────────────────────────────
+
+    /// Edits on a file with an unbalanced brace are refused.
+    #[test]
+    fn edit_refused_on_syntax_error() {
+        let content = edit("{\n  a = {\n    b = 1;\n}\n", |file| {
+            let option = Option::new("a.b");
+            assert!(matches!(
+                option.set(file, "2"),
+                Err(mx::ErrorKind::InvalidFile)
+            ));
+            assert!(matches!(
+                option.set_option_to_default(file),
+                Err(mx::ErrorKind::InvalidFile)
+            ));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = {\n    b = 1;\n}\n");
+    }
+
+    /// The comments above a definition are kept when the removal is refused.
+    #[test]
+    fn reset_with_comments_refused_on_syntax_error() {
+        let content = edit("{\n  # about a\n  a = 1;\n  b = ;\n}\n", |file| {
+            assert!(matches!(
+                Option::new("a").set_option_to_default_with_comments(file),
+                Err(mx::ErrorKind::InvalidFile)
+            ));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  # about a\n  a = 1;\n  b = ;\n}\n");
+    }
+
+    /// `with_force` allows editing a broken file anyway.
+    #[test]
+    fn edit_forced_on_syntax_error() {
+        let content = edit("{\n  a = {\n    b = 1;\n}\n", |file| {
+            Option::new("a.b").with_force(true).set(file, "2")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(content, "{\n  a = {\n    b = 2;\n}\n");
+    }
+}
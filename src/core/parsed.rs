@@ -0,0 +1,84 @@
+use rnix::ast::{Expr, List};
+use rnix::{TextRange, TextSize};
+use rowan::ast::AstNode;
+
+use crate::core::localise_option::SettingsPosition;
+use crate::mx;
+
+/// A Nix source parsed once, for read queries on many options of the same
+/// file. Edits change the offsets, so they still go through [`super::option::Option`].
+#[allow(dead_code)]
+pub struct ParsedNix {
+    source: String,
+    syntax: rnix::SyntaxNode,
+}
+
+#[allow(dead_code)]
+impl ParsedNix {
+    pub fn new(source: impl Into<String>) -> Self {
+        let source = source.into();
+        let syntax = rnix::Root::parse(&source).syntax();
+        ParsedNix { source, syntax }
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    fn position(&self, nix_option: &str) -> mx::Result<SettingsPosition> {
+        SettingsPosition::new(&self.syntax, nix_option)
+    }
+
+    /// Returns the source text of the value of `nix_option`.
+    pub fn get_option(&self, nix_option: &str) -> mx::Result<&str> {
+        match self.position(nix_option)? {
+            SettingsPosition::ExistingOption(option) => self
+                .source
+                .get(option.get_range_option_value().clone())
+                .ok_or(mx::ErrorKind::InvalidFile),
+            SettingsPosition::NewInsertion(_) => Err(mx::ErrorKind::OptionNotFound),
+        }
+    }
+
+    /// Returns the part of `nix_option` which is not defined in the source,
+    /// `None` if the option is defined.
+    pub fn remaining_path(&self, nix_option: &str) -> mx::Result<Option<String>> {
+        Ok(match self.position(nix_option)? {
+            SettingsPosition::ExistingOption(_) => None,
+            SettingsPosition::NewInsertion(insertion) => {
+                Some(insertion.get_remaining_path().to_string())
+            }
+        })
+    }
+
+    /// Returns the source text of each element of the list `nix_option`.
+    pub fn list_elements(&self, nix_option: &str) -> mx::Result<Vec<&str>> {
+        let SettingsPosition::ExistingOption(option) = self.position(nix_option)? else {
+            return Err(mx::ErrorKind::OptionNotFound);
+        };
+        let range = option.get_range_option_value();
+        let text_range = TextRange::new(
+            TextSize::from(range.start as u32),
+            TextSize::from(range.end as u32),
+        );
+        let list = self
+            .syntax
+            .covering_element(text_range)
+            .into_node()
+            .and_then(|node| node.ancestors().find_map(List::cast))
+            .filter(|list| list.syntax().text_range() == text_range)
+            .ok_or(mx::ErrorKind::OptionIsNotList)?;
+        list.items()
+            .map(|item: Expr| {
+                let item_range = item.syntax().text_range();
+                self.source
+                    .get(usize::from(item_range.start())..usize::from(item_range.end()))
+                    .ok_or(mx::ErrorKind::InvalidFile)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[path = "parsed_tests.rs"]
+mod tests;
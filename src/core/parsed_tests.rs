@@ -0,0 +1,69 @@
+/// Tests for [`ParsedNix`].
+///
+/// # Structure
+/// - `unit` – Read queries on an in-memory Nix source.
+use super::ParsedNix;
+use crate::mx;
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Unit tests
+// ─────────────────────────────────────────────────────────────────────────────
+mod unit {
+    use super::*;
+
+    const CONFIGURATION: &str = "{ pkgs, ... }:\n{\n  networking.hostName = \"nixos\";\n  time.timeZone = \"Europe/Paris\";\n  services.openssh = {\n    enable = true;\n    ports = [ 22 2222 ];\n  };\n  environment.systemPackages = with pkgs; [ vim (import ./x.nix) ];\n}\n";
+
+    /// Several options are read from a single parse.
+    #[test]
+    fn reads_many_options() {
+        let parsed = ParsedNix::new(CONFIGURATION);
+        assert_eq!(
+            parsed.get_option("networking.hostName").unwrap(),
+            "\"nixos\""
+        );
+        assert_eq!(
+            parsed.get_option("time.timeZone").unwrap(),
+            "\"Europe/Paris\""
+        );
+        assert_eq!(
+            parsed.get_option("services.openssh.enable").unwrap(),
+            "true"
+        );
+        assert_eq!(
+            parsed.list_elements("services.openssh.ports").unwrap(),
+            ["22", "2222"]
+        );
+        assert_eq!(
+            parsed.list_elements("environment.systemPackages").unwrap(),
+            ["vim", "(import ./x.nix)"]
+        );
+    }
+
+    /// `remaining_path` is `None` for a defined option, the undefined part otherwise.
+    #[test]
+    fn remaining_path() {
+        let parsed = ParsedNix::new(CONFIGURATION);
+        assert_eq!(
+            parsed.remaining_path("services.openssh.enable").unwrap(),
+            None
+        );
+        assert_eq!(
+            parsed.remaining_path("services.openssh.banner").unwrap(),
+            Some(String::from("banner"))
+        );
+    }
+
+    /// Missing options and non-list values are reported.
+    #[test]
+    fn errors() {
+        let parsed = ParsedNix::new(CONFIGURATION);
+        assert!(matches!(
+            parsed.get_option("boot.loader"),
+            Err(mx::ErrorKind::OptionNotFound)
+        ));
+        assert!(matches!(
+            parsed.list_elements("networking.hostName"),
+            Err(mx::ErrorKind::OptionIsNotList)
+        ));
+    }
+}
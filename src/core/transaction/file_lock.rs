@@ -1,6 +1,10 @@
+use crate::core::option::Option as mxOption;
+use crate::core::utils::line_diff;
 use crate::mx;
 use std::{
+    borrow::Cow,
     fs::{self, File},
+    hash::{DefaultHasher, Hash, Hasher},
     io::{self, Read, Seek, Write},
 };
 
@@ -32,6 +36,32 @@ pub struct NixFile {
 
     /// Indique si le fichier a été créé par `create_file` (absent au départ).
     was_created: bool,
+
+    /// Empreinte du contenu lu sur disque au `begin` (ou au dernier `reload`),
+    /// comparée au contenu courant lors du `commit`.
+    original_hash: u64,
+
+    /// Contenu brut lu sur disque au `begin` (ou au dernier `reload`), réécrit
+    /// par `write_original` lors d'un rollback.
+    original_content: String,
+
+    /// Indique si le fichier utilise majoritairement des fins de ligne CRLF.
+    /// Le contenu en mémoire est alors converti en LF au chargement, et
+    /// reconverti en CRLF à l'écriture.
+    crlf: bool,
+
+    /// Indique si le contenu sur disque a été réécrit par `write` depuis le
+    /// `begin` ; seul un tel fichier est restauré par `write_original`.
+    written: bool,
+}
+
+/// Copie du contenu en mémoire d'un [`NixFile`], prise par [`NixFile::checkpoint`].
+///
+/// Permet d'annuler les modifications d'un seul fichier sans annuler toute la
+/// transaction, via [`NixFile::restore`].
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    content: String,
 }
 
 impl NixFile {
@@ -48,6 +78,10 @@ impl NixFile {
             path: String::from(repo_path) + relative_path,
             file_content: String::new(),
             was_created: false,
+            original_hash: 0,
+            original_content: String::new(),
+            crlf: false,
+            written: false,
         }
     }
 
@@ -199,6 +233,8 @@ impl NixFile {
 
     /// Retourne une référence partagée sur le contenu du fichier en mémoire.
     ///
+    /// Les fins de ligne y sont toujours des `\n`, y compris pour un fichier CRLF.
+    ///
     /// # Erreurs
     /// Retourne `mx::ErrorKind::TransactionNotBegin` si aucune transaction n'est active.
     pub fn get_file_content(&self) -> mx::Result<&String> {
@@ -208,6 +244,93 @@ impl NixFile {
         Ok(&self.file_content)
     }
 
+    /// Retourne la valeur brute (texte source) de l'option `path` dans le contenu
+    /// en mémoire, ou `None` si l'option n'est pas définie.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::TransactionNotBegin` – Aucune transaction active.
+    /// * Toute erreur de localisation de l'option (chemin invalide, fichier
+    ///   illisible…).
+    #[allow(dead_code)]
+    pub fn get_option(&self, path: &str) -> mx::Result<Option<String>> {
+        match mxOption::new(path).get_in_str(self.get_file_content()?) {
+            Ok(value) => Ok(Some(value.to_string())),
+            Err(mx::ErrorKind::OptionNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Définit l'option `path` à `value` (texte Nix brut) dans le contenu en
+    /// mémoire. Rien n'est écrit sur le disque avant le `commit`.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::TransactionNotBegin` – Aucune transaction active.
+    /// * Toute erreur renvoyée par [`mxOption::set_in_str`].
+    #[allow(dead_code)]
+    pub fn set_option(&mut self, path: &str, value: &str) -> mx::Result<()> {
+        mxOption::new(path).set_in_str(self.get_mut_file_content()?, value)?;
+        Ok(())
+    }
+
+    /// Diff unifié ligne à ligne entre le contenu lu au `begin` (ou au dernier
+    /// `reload`) et le contenu en mémoire. Chaîne vide si rien n'a changé.
+    ///
+    /// # Erreurs
+    /// Retourne `mx::ErrorKind::TransactionNotBegin` si aucune transaction n'est active.
+    pub fn diff(&self) -> mx::Result<String> {
+        Ok(line_diff(&self.original_content, self.get_file_content()?))
+    }
+
+    /// Capture le contenu actuel du fichier en mémoire.
+    ///
+    /// # Erreurs
+    /// Retourne `mx::ErrorKind::TransactionNotBegin` si aucune transaction n'est active.
+    #[allow(dead_code)]
+    pub fn checkpoint(&self) -> mx::Result<Checkpoint> {
+        Ok(Checkpoint {
+            content: self.get_file_content()?.clone(),
+        })
+    }
+
+    /// Remet le contenu en mémoire dans l'état capturé par `checkpoint`.
+    ///
+    /// Seul le contenu en mémoire est modifié : rien n'est écrit sur le disque
+    /// avant le `commit`.
+    ///
+    /// # Erreurs
+    /// Retourne `mx::ErrorKind::TransactionNotBegin` si aucune transaction n'est active.
+    #[allow(dead_code)]
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> mx::Result<()> {
+        *self.get_mut_file_content()? = checkpoint.content;
+        Ok(())
+    }
+
+    /// Relit le fichier verrouillé depuis le début pour remplacer le contenu en
+    /// mémoire, afin de prendre en compte une modification faite par un autre
+    /// processus avant l'édition.
+    ///
+    /// Les modifications en mémoire non committées sont perdues.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::TransactionNotBegin` – Aucune transaction active.
+    /// * `mx::ErrorKind::IOError` – Échec de la relecture.
+    #[allow(dead_code)]
+    pub fn reload(&mut self) -> mx::Result<()> {
+        let f = self
+            .file
+            .as_mut()
+            .ok_or(mx::ErrorKind::TransactionNotBegin)?;
+        f.seek(io::SeekFrom::Start(0))
+            .map_err(mx::ErrorKind::IOError)?;
+        let mut content = String::new();
+        f.read_to_string(&mut content)
+            .map_err(mx::ErrorKind::IOError)?;
+        self.original_hash = Self::content_hash(content.as_bytes());
+        self.original_content = content.clone();
+        self.load_content(content);
+        Ok(())
+    }
+
     /// Ouvre une transaction sur le fichier : retire le flag immutable, pose un verrou
     /// exclusif et charge le contenu en mémoire dans `file_content`.
     ///
@@ -254,8 +377,13 @@ impl NixFile {
         // Pose un verrou exclusif puis lit le contenu intégral en mémoire
         if let Some(f) = self.file.as_mut() {
             f.lock().or(Err(mx::ErrorKind::FailToLock))?;
-            f.read_to_string(&mut self.file_content)
+            let mut content = String::new();
+            f.read_to_string(&mut content)
                 .map_err(mx::ErrorKind::IOError)?;
+            self.original_hash = Self::content_hash(content.as_bytes());
+            self.original_content = content.clone();
+            self.load_content(content);
+            self.written = false;
             Ok(())
         } else {
             Err(mx::ErrorKind::InvalidFile)
@@ -268,29 +396,66 @@ impl NixFile {
     /// Le fichier est tronqué à zéro avant réécriture pour éviter tout résidu si le
     /// nouveau contenu est plus court que l'ancien.
     ///
+    /// Le contenu sur disque est d'abord comparé à celui lu au `begin` : si un
+    /// autre processus l'a modifié entre-temps, rien n'est écrit.
+    ///
     /// # Erreurs
     /// * `mx::ErrorKind::InvalidFile` – Aucune transaction active.
+    /// * `mx::ErrorKind::FileChangedExternally` – Le fichier a changé sur disque
+    ///   depuis le `begin` ; la transaction reste active.
     /// * `mx::ErrorKind::PermissionDenied` – Échec de l'écriture.
+    #[allow(dead_code)]
     pub(super) fn commit(&mut self) -> mx::Result<()> {
+        self.write()?;
+        self.release()
+    }
+
+    /// Première moitié de [`commit`](Self::commit) : écrit le contenu en mémoire
+    /// sur disque mais garde le fichier ouvert et verrouillé, pour qu'un
+    /// rollback puisse encore réécrire l'original via [`write_original`](Self::write_original).
+    /// [`release`](Self::release) termine ensuite la transaction.
+    ///
+    /// # Erreurs
+    /// Identiques à celles de [`commit`](Self::commit).
+    pub(super) fn write(&mut self) -> mx::Result<()> {
+        self.write_impl(true)
+    }
+
+    /// Identique à [`write`](Self::write), sans vérifier que le fichier n'a
+    /// pas changé sur disque : les modifications externes sont écrasées.
+    pub(super) fn force_write(&mut self) -> mx::Result<()> {
+        self.write_impl(false)
+    }
+
+    /// Réécrit sur disque le contenu lu au `begin`, en gardant le verrou.
+    /// Sans effet si aucune transaction n'est active ou si `write` n'a pas
+    /// touché au fichier : une modification externe refusée par `write` est
+    /// ainsi conservée.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::IOError` – Échec du positionnement ou de la troncature.
+    /// * `mx::ErrorKind::PermissionDenied` – Échec de l'écriture.
+    pub(super) fn write_original(&mut self) -> mx::Result<()> {
+        let Some(f) = self.file.as_mut().filter(|_| self.written) else {
+            return Ok(());
+        };
+        f.seek(io::SeekFrom::Start(0))
+            .map_err(mx::ErrorKind::IOError)?;
+        f.set_len(0).map_err(mx::ErrorKind::IOError)?;
+        f.write_all(self.original_content.as_bytes())
+            .or(Err(mx::ErrorKind::PermissionDenied))
+    }
+
+    /// Seconde moitié de [`commit`](Self::commit) : remet le flag immutable,
+    /// libère le verrou et termine la transaction.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::InvalidFile` – Aucune transaction active.
+    pub(super) fn release(&mut self) -> mx::Result<()> {
         if self.file.is_none() {
             return Err(mx::ErrorKind::InvalidFile);
         }
 
-        // Retour au début du fichier, puis troncature pour repartir de zéro
-        self.file
-            .as_mut()
-            .unwrap()
-            .seek(io::SeekFrom::Start(0))
-            .unwrap();
-        self.file.as_ref().unwrap().set_len(0).unwrap();
-
-        // Écriture du contenu modifié
-        self.file
-            .as_ref()
-            .unwrap()
-            .write_all(&self.file_content.as_bytes())
-            .or(Err(mx::ErrorKind::PermissionDenied))?;
-
         // Protection du fichier et libération du verrou
         Self::make_immutable(&self.path)?;
         self.file
@@ -303,10 +468,70 @@ impl NixFile {
         // Sans ceci, file.is_some() resterait vrai et get_file_content()
         // continuerait de retourner Ok au lieu de TransactionNotBegin.
         self.file_content = String::new();
+        self.original_content = String::new();
+        self.written = false;
         self.file = None;
         Ok(())
     }
 
+    /// Charge `content` lu sur disque dans `file_content`. Si les fins de ligne
+    /// CRLF sont majoritaires, elles sont converties en LF pour que l'édition
+    /// n'insère jamais de `\n` isolé ; `commit` les rétablit.
+    fn load_content(&mut self, content: String) {
+        let crlf_count = content.matches("\r\n").count();
+        self.crlf = crlf_count > content.matches('\n').count() - crlf_count;
+        self.file_content = if self.crlf {
+            content.replace("\r\n", "\n")
+        } else {
+            content
+        };
+    }
+
+    fn content_hash(bytes: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn write_impl(&mut self, check_drift: bool) -> mx::Result<()> {
+        if self.file.is_none() {
+            return Err(mx::ErrorKind::InvalidFile);
+        }
+
+        if check_drift {
+            let f = self.file.as_mut().unwrap();
+            let mut current = Vec::new();
+            f.seek(io::SeekFrom::Start(0))
+                .map_err(mx::ErrorKind::IOError)?;
+            f.read_to_end(&mut current)
+                .map_err(mx::ErrorKind::IOError)?;
+            if Self::content_hash(&current) != self.original_hash {
+                return Err(mx::ErrorKind::FileChangedExternally);
+            }
+        }
+
+        // Retour au début du fichier, puis troncature pour repartir de zéro
+        self.written = true;
+        self.file
+            .as_mut()
+            .unwrap()
+            .seek(io::SeekFrom::Start(0))
+            .unwrap();
+        self.file.as_ref().unwrap().set_len(0).unwrap();
+
+        // Écriture du contenu modifié, avec les fins de ligne d'origine
+        let content = if self.crlf {
+            Cow::Owned(self.file_content.replace('\n', "\r\n"))
+        } else {
+            Cow::Borrowed(&self.file_content)
+        };
+        self.file
+            .as_ref()
+            .unwrap()
+            .write_all(content.as_bytes())
+            .or(Err(mx::ErrorKind::PermissionDenied))
+    }
+
     /// Annule la transaction sans persister les modifications : libère le verrou,
     /// vide le contenu en mémoire et ferme le handle.
     ///
@@ -322,6 +547,8 @@ impl NixFile {
             f.unlock();
         }
         self.file_content = String::new();
+        self.original_content = String::new();
+        self.written = false;
         self.file = None;
         Ok(())
     }
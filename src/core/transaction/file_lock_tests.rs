@@ -56,6 +56,20 @@ mod unit {
 
     // ── get_file_content / get_mut_file_content ───────────────────────────────
 
+    /// `get_option` / `set_option` require an active transaction.
+    #[test]
+    fn option_access_without_transaction_errors() {
+        let mut f = NixFile::new("/repo", "/file.nix");
+        assert!(matches!(
+            f.get_option("a"),
+            Err(mx::ErrorKind::TransactionNotBegin)
+        ));
+        assert!(matches!(
+            f.set_option("a", "1"),
+            Err(mx::ErrorKind::TransactionNotBegin)
+        ));
+    }
+
     /// Reading content without an active transaction returns `TransactionNotBegin`.
     #[test]
     fn get_file_content_without_transaction_errors() {
@@ -97,6 +111,26 @@ mod unit {
         assert!(matches!(f.commit(), Err(mx::ErrorKind::InvalidFile)));
     }
 
+    /// `checkpoint` without an active transaction returns `TransactionNotBegin`.
+    #[test]
+    fn checkpoint_without_transaction_errors() {
+        let f = NixFile::new("/etc/nixos", "/configuration.nix");
+        assert!(matches!(
+            f.checkpoint(),
+            Err(mx::ErrorKind::TransactionNotBegin)
+        ));
+    }
+
+    /// `reload` without an active transaction returns `TransactionNotBegin`.
+    #[test]
+    fn reload_without_transaction_errors() {
+        let mut f = NixFile::new("/etc/nixos", "/configuration.nix");
+        assert!(matches!(
+            f.reload(),
+            Err(mx::ErrorKind::TransactionNotBegin)
+        ));
+    }
+
     /// `was_created` stays `false` if `create_file` is never called.
     #[test]
     fn was_created_stays_false_without_create_file() {
@@ -229,6 +263,187 @@ mod integration {
         );
     }
 
+    /// `restore` brings back the content captured by `checkpoint`, and only
+    /// the committed content reaches the disk.
+    #[test]
+    fn checkpoint_restore_reverts_modifications() {
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        fs::write(format!("{}/config.nix", path), "original content").unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        f.get_mut_file_content().unwrap().push_str(" kept");
+        let checkpoint = f.checkpoint().unwrap();
+        *f.get_mut_file_content().unwrap() = String::from("discarded");
+        f.restore(checkpoint).unwrap();
+        assert_eq!(f.get_file_content().unwrap(), "original content kept");
+        f.commit().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.nix", path)).unwrap(),
+            "original content kept"
+        );
+    }
+
+    /// `set_option` edits the buffer only: both options are readable through
+    /// `get_option` before `commit`, while the disk is untouched.
+    #[test]
+    fn set_option_edits_buffer_before_commit() {
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        fs::write(format!("{}/config.nix", path), "{\n}\n").unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        f.set_option("networking.hostName", "\"host\"").unwrap();
+        f.set_option("boot.loader.timeout", "5").unwrap();
+        assert_eq!(
+            f.get_option("networking.hostName").unwrap().as_deref(),
+            Some("\"host\"")
+        );
+        assert_eq!(
+            f.get_option("boot.loader.timeout").unwrap().as_deref(),
+            Some("5")
+        );
+        assert_eq!(f.get_option("absent.option").unwrap(), None);
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.nix", path)).unwrap(),
+            "{\n}\n"
+        );
+        f.close().unwrap();
+    }
+
+    /// A CRLF file is edited with LF in memory and written back with CRLF
+    /// only: no lone `\n` or `\r` is introduced.
+    #[test]
+    fn commit_preserves_crlf_line_endings() {
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        let original = "{\r\n  a = 1;\r\n}\r\n";
+        fs::write(format!("{}/config.nix", path), original).unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        assert_eq!(f.get_file_content().unwrap(), "{\n  a = 1;\n}\n");
+        f.set_option("b.c", "2").unwrap();
+        f.set_option("a", "3").unwrap();
+        f.commit().unwrap();
+
+        let written = fs::read_to_string(format!("{}/config.nix", path)).unwrap();
+        assert_eq!(
+            written,
+            "{\r\n  a = 3;\r\n  b = {\r\n    c = 2;\r\n  };\r\n}\r\n"
+        );
+        assert_eq!(
+            written.matches('\n').count(),
+            written.matches("\r\n").count()
+        );
+        assert_eq!(
+            written.matches('\r').count(),
+            written.matches("\r\n").count()
+        );
+    }
+
+    /// A CRLF file committed without modification is left byte for byte.
+    #[test]
+    fn commit_unchanged_crlf_file_is_identical() {
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        let original = "{\r\n  a = 1;\r\n}\r\n";
+        fs::write(format!("{}/config.nix", path), original).unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        f.commit().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.nix", path)).unwrap(),
+            original
+        );
+    }
+
+    /// `reload` replaces the in-memory content with the current disk content.
+    #[test]
+    fn reload_picks_up_external_change() {
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        fs::write(format!("{}/config.nix", path), "original content").unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        f.get_mut_file_content().unwrap().push_str(" edited");
+        fs::write(format!("{}/config.nix", path), "external").unwrap();
+        f.reload().unwrap();
+        assert_eq!(f.get_file_content().unwrap(), "external");
+        f.close().unwrap();
+    }
+
+    /// `commit` refuses to overwrite a file modified on disk since `begin`,
+    /// `force_write` overwrites it.
+    #[test]
+    fn commit_refuses_external_modification() {
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        fs::write(format!("{}/config.nix", path), "original content").unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        *f.get_mut_file_content().unwrap() = String::from("modified content");
+        fs::write(format!("{}/config.nix", path), "external").unwrap();
+
+        assert!(matches!(
+            f.commit(),
+            Err(mx::ErrorKind::FileChangedExternally)
+        ));
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.nix", path)).unwrap(),
+            "external"
+        );
+
+        f.force_write().unwrap();
+        f.release().unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.nix", path)).unwrap(),
+            "modified content"
+        );
+    }
+
+    /// `write_original` restores a file written by `write`, but keeps an
+    /// external modification that `write` refused to overwrite.
+    #[test]
+    fn write_original_only_restores_written_file() {
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        fs::write(format!("{}/config.nix", path), "original content").unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        *f.get_mut_file_content().unwrap() = String::from("modified content");
+        fs::write(format!("{}/config.nix", path), "external").unwrap();
+        assert!(matches!(
+            f.write(),
+            Err(mx::ErrorKind::FileChangedExternally)
+        ));
+        f.write_original().unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.nix", path)).unwrap(),
+            "external"
+        );
+        f.close().unwrap();
+
+        fs::write(format!("{}/config.nix", path), "original content").unwrap();
+        f.begin().unwrap();
+        *f.get_mut_file_content().unwrap() = String::from("modified content");
+        f.write().unwrap();
+        f.write_original().unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.nix", path)).unwrap(),
+            "original content"
+        );
+        f.close().unwrap();
+    }
+
     /// `commit` correctly truncates when the new content is shorter.
     #[test]
     fn commit_truncates_when_content_is_shorter() {
@@ -306,6 +521,28 @@ mod integration {
         );
     }
 
+    /// `commit` rewrites the file in place: its mode and ownership are kept.
+    #[test]
+    fn commit_preserves_mode_and_owner() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tmp_dir();
+        let path = dir.path().to_str().unwrap();
+        let file_path = format!("{}/config.nix", path);
+        fs::write(&file_path, "original content").unwrap();
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o600)).unwrap();
+        let before = fs::metadata(&file_path).unwrap();
+
+        let mut f = NixFile::new(path, "/config.nix");
+        f.begin().unwrap();
+        *f.get_mut_file_content().unwrap() = String::from("modified content");
+        f.commit().unwrap();
+
+        let after = fs::metadata(&file_path).unwrap();
+        assert_eq!(after.permissions().mode() & 0o777, 0o600);
+        assert_eq!((after.uid(), after.gid()), (before.uid(), before.gid()));
+    }
+
     /// After `commit`, the transaction is closed.
     #[test]
     fn commit_ends_transaction() {
@@ -54,7 +54,25 @@ pub fn make_transaction<F, R>(
 where
     F: FnOnce(&mut NixFile) -> mx::Result<R>,
 {
-    let mut transaction = Transaction::new(config_dir, description, build_command)?;
+    make_transaction_with(
+        Transaction::new(config_dir, description, build_command)?,
+        file_path,
+        f,
+    )
+}
+
+/// Identique à [`make_transaction`], sur une [`Transaction`] déjà construite
+/// par l'appelant (signature, programme de reconstruction, verrous…).
+///
+/// La transaction ne doit pas encore être ouverte.
+pub fn make_transaction_with<F, R>(
+    mut transaction: Transaction<'_>,
+    file_path: &str,
+    f: F,
+) -> mx::Result<R>
+where
+    F: FnOnce(&mut NixFile) -> mx::Result<R>,
+{
     transaction.add_file(file_path)?;
     transaction.begin()?;
 
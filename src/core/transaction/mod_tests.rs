@@ -21,7 +21,8 @@
 /// [dev-dependencies]
 /// tempfile = "3"
 /// ```
-use super::{BuildCommand, make_transaction};
+use super::{BuildCommand, make_transaction, make_transaction_with};
+use crate::core::transaction::{Transaction, transaction::LockPaths};
 use crate::mx;
 use std::fs;
 use tempfile::TempDir;
@@ -34,9 +35,9 @@ fn repo_path(dir: &TempDir) -> String {
     format!("{}/", dir.path().to_str().unwrap())
 }
 
-/// Returns a `BuildCommand` that runs no actual build (empty command in release,
-/// `build-vm` in debug — but no `flake.nix` means the build is never triggered
-/// because `commit_impl` skips the build when there is no diff).
+/// Returns a `BuildCommand` that runs no actual build (no `flake.nix` means
+/// the build is never triggered because `commit_impl` skips the build when
+/// there is no diff).
 fn noop_build() -> BuildCommand {
     BuildCommand::Install
 }
@@ -88,23 +89,14 @@ fn create_and_commit(dir: &TempDir, file_name: &str, content: &str) -> std::path
     file_path
 }
 
-/// Acquires the build-queue lock so that `commit_impl` skips the NixOS rebuild.
-///
-/// Returns the lock file handle — it **must** stay alive for the duration of
-/// the test (dropping it releases the lock).  Usage:
-/// ```rust
-/// let _guard = lock_build_queue();
-/// make_transaction(...)?;
-/// ```
-fn lock_build_queue() -> fs::File {
-    let f = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open("/tmp/mx-queue-build.lock")
-        .expect("failed to create build-queue lock file");
-    f.lock().expect("failed to lock build-queue lock file");
-    f
+/// Returns a transaction on the repo at `path` whose rebuild is `true` (a
+/// no-op), with build locks kept in the repo's `.git` directory so that tests
+/// never wait on each other. Needed by every transaction producing a diff.
+fn noop_transaction(path: &str, description: &str) -> Transaction<'static> {
+    Transaction::new(path, description, noop_build())
+        .unwrap()
+        .with_rebuild_program("true")
+        .with_lock_paths(LockPaths::in_dir(&format!("{}.git", path)))
 }
 
 // ─────────────────────────────────────────────────────────────────────────────
@@ -161,13 +153,12 @@ mod integration {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "test.nix", "");
-        // Hold the build-queue lock so commit_impl skips the NixOS rebuild.
-        let _guard = lock_build_queue();
 
-        let result = make_transaction("test commit", &path, "test.nix", noop_build(), |file| {
-            file.get_mut_file_content()?.push_str("# modified\n");
-            Ok(42usize)
-        });
+        let result =
+            make_transaction_with(noop_transaction(&path, "test commit"), "test.nix", |file| {
+                file.get_mut_file_content()?.push_str("# modified\n");
+                Ok(42usize)
+            });
 
         assert_eq!(result.unwrap(), 42);
         assert!(
@@ -196,7 +187,6 @@ mod integration {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "vec.nix", "line1\nline2\n");
-        let _guard = lock_build_queue();
 
         let result: mx::Result<Vec<String>> =
             make_transaction("vec return", &path, "vec.nix", noop_build(), |file| {
@@ -281,12 +271,15 @@ mod integration {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "content.nix", "before");
-        let _guard = lock_build_queue();
 
-        make_transaction::<_, ()>("write test", &path, "content.nix", noop_build(), |file| {
-            *file.get_mut_file_content()? = String::from("after");
-            Ok(())
-        })
+        make_transaction_with::<_, ()>(
+            noop_transaction(&path, "write test"),
+            "content.nix",
+            |file| {
+                *file.get_mut_file_content()? = String::from("after");
+                Ok(())
+            },
+        )
         .unwrap();
 
         assert_eq!(
@@ -311,7 +304,6 @@ mod no_regression {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "f.nix", "v1");
-        let _guard = lock_build_queue();
 
         make_transaction::<_, ()>("tx1", &path, "f.nix", noop_build(), |_| Ok(())).unwrap();
         make_transaction::<_, ()>("tx2", &path, "f.nix", noop_build(), |_| Ok(())).unwrap();
@@ -326,7 +318,6 @@ mod no_regression {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "f.nix", "original");
-        let _guard = lock_build_queue();
 
         // First transaction: deliberate failure
         let _ = make_transaction::<_, ()>("fail", &path, "f.nix", noop_build(), |_| {
@@ -351,7 +342,6 @@ mod no_regression {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "f.nix", "clean");
-        let _guard = lock_build_queue();
 
         let _ = make_transaction::<_, ()>("poison", &path, "f.nix", noop_build(), |file| {
             *file.get_mut_file_content()? = String::from("# poison");
@@ -375,7 +365,6 @@ mod no_regression {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "res.nix", "data");
-        let _guard = lock_build_queue();
 
         for _ in 0..3 {
             let _ = make_transaction::<_, ()>("iter", &path, "res.nix", noop_build(), |_| {
@@ -404,7 +393,6 @@ mod stash {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "target.nix", "original");
-        let _guard = lock_build_queue();
 
         // untracked bystander — must NOT be committed, so make_transaction stashes it
         let bystander = dir.path().join("bystander.nix");
@@ -449,11 +437,10 @@ mod stash {
         let dir = setup_repo();
         let path = repo_path(&dir);
         create_and_commit(&dir, "target.nix", "original");
-        let _guard = lock_build_queue();
         // untracked bystander triggers the stash
         fs::write(dir.path().join("bystander.nix"), "bystander").unwrap();
 
-        make_transaction::<_, ()>("tx", &path, "target.nix", noop_build(), |file| {
+        make_transaction_with::<_, ()>(noop_transaction(&path, "tx"), "target.nix", |file| {
             *file.get_mut_file_content()? = String::from("modified");
             Ok(())
         })
@@ -1,4 +1,10 @@
-use std::{collections::HashMap, fs, path, process};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path, process, thread,
+    time::{Duration, Instant},
+};
 
 use super::file_lock::NixFile;
 use crate::{CONFIG_NAME, core::list::List as mxList, mx};
@@ -12,16 +18,28 @@ const LOCK_QUEUE_BUILD_FILE: &str = "/tmp/mx-queue-build.lock";
 
 /// Commande `nixos-rebuild` (ou `nixos-install`) à exécuter après un commit réussi.
 ///
-/// En mode `debug` (sans `--release`), toutes les variantes déclenchent `build-vm`
-/// pour éviter de modifier le système hôte pendant le développement.
+/// La sous-commande ne dépend pas du profil de compilation : pour ne pas
+/// modifier le système hôte, l'appelant choisit explicitement [`BuildCommand::BuildVm`].
 #[derive(Clone)]
 pub enum BuildCommand {
     /// Reconstruit le système et bascule immédiatement (`nixos-rebuild switch`).
     Switch,
     /// Prépare le prochain démarrage sans redémarrer (`nixos-rebuild boot`).
     Boot,
+    /// Construit le système sans l'activer (`nixos-rebuild build`).
+    #[allow(dead_code)]
+    Build,
+    /// Construit une machine virtuelle de test (`nixos-rebuild build-vm`).
+    BuildVm,
+    /// Affiche ce que l'activation changerait sans rien appliquer
+    /// (`nixos-rebuild dry-activate`). Aucun commit Git n'est créé.
+    #[allow(dead_code)]
+    DryActivate,
+    /// Active le système sans l'ajouter au chargeur de démarrage
+    /// (`nixos-rebuild test`).
+    #[allow(dead_code)]
+    Test,
     /// Installation initiale sur une nouvelle machine (`nixos-install`).
-    /// La commande de build est vide en release ; déclenche `build-vm` en debug.
     Install,
 }
 
@@ -32,16 +50,38 @@ pub enum BuildCommand {
 /// Verrou de fichier utilisé pour sérialiser les builds NixOS.
 ///
 /// Le verrou est acquis à la création via [`LockFile::lock`] ou [`LockFile::try_lock`]
-/// et libéré explicitement via [`LockFile::unlock`]. Si `unlock` n'est pas appelé,
-/// le verrou est libéré par le noyau à la fermeture du processus (mais pas du `File`
-/// Rust — préférer `unlock` explicite).
+/// et libéré via [`LockFile::unlock`], appelé aussi par `drop` : un verrou
+/// abandonné sur une erreur ne laisse pas son PID dans le fichier.
+///
+/// Le PID du détenteur est écrit dans le fichier une fois le verrou acquis et
+/// effacé par `unlock`, cf. [`LockFile::holder`].
 struct LockFile {
     /// Handle vers le fichier verrouillé. `None` après un `unlock`.
     file: Option<fs::File>,
 }
 
 impl LockFile {
-    /// Crée (ou tronque) le fichier à `path` et pose un verrou exclusif bloquant.
+    /// Ouvre le fichier à `path`, créé si besoin. Il n'est pas tronqué : le
+    /// PID du détenteur actuel doit rester lisible tant que le verrou n'est
+    /// pas acquis.
+    fn open(path: &str) -> mx::Result<fs::File> {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(mx::ErrorKind::IOError)
+    }
+
+    /// Remplace le contenu du fichier, verrou tenu, par le PID du processus.
+    fn acquired(file: fs::File) -> mx::Result<Self> {
+        file.set_len(0).map_err(mx::ErrorKind::IOError)?;
+        writeln!(&file, "{}", process::id()).map_err(mx::ErrorKind::IOError)?;
+        Ok(LockFile { file: Some(file) })
+    }
+
+    /// Crée le fichier à `path` si besoin et pose un verrou exclusif bloquant.
     ///
     /// Bloque jusqu'à l'acquisition du verrou.
     ///
@@ -49,15 +89,11 @@ impl LockFile {
     /// * `mx::ErrorKind::FailToLock` – Impossible de verrouiller.
     /// * `mx::ErrorKind::IOError`    – Impossible de créer le fichier.
     pub fn lock(path: &str) -> mx::Result<Self> {
-        Ok(LockFile {
-            file: match fs::File::create(path) {
-                Ok(f) => match f.lock() {
-                    Ok(_) => Some(f),
-                    Err(_) => return Err(mx::ErrorKind::FailToLock),
-                },
-                Err(e) => return Err(mx::ErrorKind::IOError(e)),
-            },
-        })
+        let file = Self::open(path)?;
+        match file.lock() {
+            Ok(_) => Self::acquired(file),
+            Err(_) => Err(mx::ErrorKind::FailToLock),
+        }
     }
 
     /// Tente de poser un verrou exclusif non-bloquant.
@@ -67,24 +103,110 @@ impl LockFile {
     /// * `Ok(None)`       – Le fichier est déjà verrouillé par un autre processus.
     /// * `Err(_)`         – Erreur I/O inattendue.
     pub fn try_lock(path: &str) -> mx::Result<Option<Self>> {
-        Ok(Some(LockFile {
-            file: match fs::File::create(path) {
-                Ok(f) => match f.try_lock() {
-                    Ok(_) => Some(f),
-                    Err(fs::TryLockError::WouldBlock) => return Ok(None),
-                    Err(_) => return Err(mx::ErrorKind::FailToLock),
-                },
-                Err(e) => return Err(mx::ErrorKind::IOError(e)),
-            },
-        }))
+        let file = Self::open(path)?;
+        match file.try_lock() {
+            Ok(_) => Self::acquired(file).map(Some),
+            Err(fs::TryLockError::WouldBlock) => Ok(None),
+            Err(_) => Err(mx::ErrorKind::FailToLock),
+        }
     }
 
-    /// Libère le verrou et ferme le handle. Sans effet si déjà déverrouillé.
+    /// Tente d'acquérir le verrou à intervalles croissants (10 ms à 500 ms)
+    /// jusqu'à expiration de `timeout`. Avec `Duration::MAX`, équivaut à [`LockFile::lock`].
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::FailToLock` – Verrou toujours tenu après `timeout`.
+    /// * `mx::ErrorKind::IOError`    – Impossible de créer le fichier.
+    pub fn lock_timeout(path: &str, timeout: Duration) -> mx::Result<Self> {
+        if timeout == Duration::MAX {
+            return Self::lock(path);
+        }
+        let start = Instant::now();
+        let mut backoff = Duration::from_millis(10);
+        loop {
+            if let Some(lock) = Self::try_lock(path)? {
+                return Ok(lock);
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(mx::ErrorKind::FailToLock);
+            }
+            thread::sleep(backoff.min(timeout - elapsed));
+            backoff = (backoff * 2).min(Duration::from_millis(500));
+        }
+    }
+
+    /// Efface le PID, libère le verrou et ferme le handle. Sans effet si déjà
+    /// déverrouillé.
     pub fn unlock(&mut self) {
-        if self.file.is_some() {
-            self.file.as_mut().unwrap().unlock().unwrap_or_default();
+        if let Some(file) = self.file.take() {
+            file.set_len(0).unwrap_or_default();
+            file.unlock().unwrap_or_default();
+        }
+    }
+
+    /// PID du processus qui tient le verrou à `path`, `None` si le verrou est
+    /// libre. Un PID laissé par un processus terminé sans `unlock` est ignoré
+    /// (plus d'entrée dans `/proc`).
+    pub fn holder(path: &str) -> Option<u32> {
+        let pid: u32 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+        path::Path::new(&format!("/proc/{}", pid))
+            .exists()
+            .then_some(pid)
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        self.unlock();
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// LockPaths – emplacement des verrous de build
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Chemins des fichiers de verrou utilisés pour sérialiser les builds.
+///
+/// Par défaut, [`LOCK_BUILD_FILE`] et [`LOCK_QUEUE_BUILD_FILE`] : tous les
+/// processus de la machine partagent alors la même file de build. Des chemins
+/// distincts permettent d'isoler deux utilisateurs ou deux suites de tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockPaths {
+    /// Verrou exclusif tenu pendant toute la durée du build.
+    pub build: String,
+    /// Verrou de file d'attente, cf. [`Transaction::commit`].
+    pub queue: String,
+}
+
+impl Default for LockPaths {
+    fn default() -> Self {
+        LockPaths {
+            build: LOCK_BUILD_FILE.to_string(),
+            queue: LOCK_QUEUE_BUILD_FILE.to_string(),
+        }
+    }
+}
+
+impl LockPaths {
+    /// PID du processus qui tient le verrou de build, `None` si aucun build
+    /// n'est en cours. Permet d'afficher « build verrouillé par le PID … ».
+    #[allow(dead_code)]
+    pub fn build_holder(&self) -> Option<u32> {
+        LockFile::holder(&self.build)
+    }
+
+    /// Place les deux verrous dans le répertoire `dir`.
+    #[allow(dead_code)]
+    pub fn in_dir(dir: &str) -> Self {
+        let dir = path::Path::new(dir);
+        LockPaths {
+            build: dir.join("mx-build.lock").to_string_lossy().into_owned(),
+            queue: dir
+                .join("mx-queue-build.lock")
+                .to_string_lossy()
+                .into_owned(),
         }
-        self.file = None;
     }
 }
 
@@ -93,32 +215,51 @@ impl LockFile {
 // ─────────────────────────────────────────────────────────────────────────────
 
 impl BuildCommand {
-    /// Retourne l'argument passé à `nixos-rebuild` pour cette commande.
+    /// Retourne l'argument passé à `nixos-rebuild` pour cette commande :
     ///
-    /// En mode release :
     /// * `Switch`  → `"switch"`
     /// * `Boot`    → `"boot"`
+    /// * `Build`   → `"build"`
+    /// * `BuildVm` → `"build-vm"`
+    /// * `DryActivate` → `"dry-activate"`
+    /// * `Test`    → `"test"`
     /// * `Install` → `""` (utilise `nixos-install` directement, cf. [`Transaction::rebuild_config`])
-    ///
-    /// En mode debug : toutes les variantes retournent `"build-vm"` pour ne pas
-    /// modifier le système hôte.
-    #[cfg(not(debug_assertions))]
     pub fn as_str(&self) -> &'static str {
         match self {
             BuildCommand::Switch => "switch",
             BuildCommand::Boot => "boot",
+            BuildCommand::Build => "build",
+            BuildCommand::BuildVm => "build-vm",
+            BuildCommand::DryActivate => "dry-activate",
+            BuildCommand::Test => "test",
             BuildCommand::Install => "",
         }
     }
+}
 
-    #[cfg(debug_assertions)]
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            BuildCommand::Switch => "build-vm",
-            BuildCommand::Boot => "build-vm",
-            BuildCommand::Install => "build-vm",
-        }
-    }
+// ─────────────────────────────────────────────────────────────────────────────
+// RebuildReport – sorties d'une reconstruction réussie
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Sorties capturées d'une reconstruction NixOS terminée avec succès.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default)]
+pub struct RebuildReport {
+    /// Sortie standard du processus de reconstruction.
+    pub stdout: String,
+    /// Sortie d'erreur du processus (avertissements, trace de build…).
+    pub stderr: String,
+}
+
+/// Modifications en attente d'un fichier de la transaction, retournées par
+/// [`Transaction::plan`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    /// Chemin du fichier relatif à la racine du dépôt.
+    pub path: String,
+    /// Diff unifié entre le contenu sur disque et le contenu en mémoire.
+    pub diff: String,
 }
 
 // ─────────────────────────────────────────────────────────────────────────────
@@ -156,6 +297,15 @@ pub struct Transaction<'a> {
     /// Chemin absolu vers la racine du dépôt Git de configuration NixOS.
     git_repo_path: String,
 
+    /// Nom de la `nixosConfiguration` du flake à reconstruire (`<dépôt>#<nom>`).
+    config_name: String,
+
+    /// Programme remplaçant `nixos-rebuild` / `nixos-install`, `None` par défaut.
+    rebuild_program: Option<String>,
+
+    /// Verrous sérialisant les builds entre transactions.
+    lock_paths: LockPaths,
+
     /// Handle vers le dépôt Git, présent uniquement pendant une transaction active.
     git_repo: Option<git2::Repository>,
 
@@ -173,6 +323,14 @@ pub struct Transaction<'a> {
     /// modifications non commitées. `None` si aucun stash n'a été nécessaire.
     /// Restauré automatiquement par [`commit`] et [`rollback`].
     stash_oid: Option<git2::Oid>,
+
+    /// OID du commit Git créé par le dernier [`commit`] réussi. `None` si aucun
+    /// commit n'a encore été fait, ou si le dernier n'avait rien à valider.
+    last_commit: Option<git2::Oid>,
+
+    /// Si vrai, [`commit`] écrase les fichiers modifiés sur disque depuis le
+    /// `begin` au lieu de retourner `mx::ErrorKind::FileChangedExternally`.
+    force: bool,
 }
 
 impl<'a> Transaction<'a> {
@@ -188,70 +346,136 @@ impl<'a> Transaction<'a> {
         config_dir: &str,
         transaction_description: &str,
         build_type: BuildCommand,
+    ) -> mx::Result<Self> {
+        Self::with_config(config_dir, transaction_description, build_type, CONFIG_NAME)
+    }
+
+    /// Identique à [`Transaction::new`], mais reconstruit la `nixosConfiguration`
+    /// `config_name` au lieu de celle par défaut.
+    ///
+    /// # Arguments
+    /// * `config_dir`               – Chemin vers le dépôt Git NixOS.
+    /// * `transaction_description`  – Message de commit Git.
+    /// * `build_type`               – Commande à exécuter après le commit.
+    /// * `config_name`              – Nom de la configuration dans le flake.
+    pub fn with_config(
+        config_dir: &str,
+        transaction_description: &str,
+        build_type: BuildCommand,
+        config_name: &str,
     ) -> mx::Result<Self> {
         Ok(Transaction {
             info: transaction_description.to_string(),
             list_file: HashMap::new(),
             git_repo: None,
             git_repo_path: config_dir.to_string(),
+            config_name: config_name.to_string(),
+            rebuild_program: None,
+            lock_paths: LockPaths::default(),
             git_user: git2::Signature::now("Modulix-OS", "modulix.os@ik-mail.com").unwrap(),
             build_type,
             old_commit: git2::Oid::zero(),
             stash_oid: None,
+            last_commit: None,
+            force: false,
         })
     }
 
+    /// Remplace le programme de reconstruction (`nixos-rebuild` / `nixos-install`)
+    /// par `program`, qui reçoit les mêmes arguments.
+    ///
+    /// Utile pour les tests ou pour passer par un wrapper (`sudo`, script…).
+    #[allow(dead_code)]
+    pub fn with_rebuild_program(mut self, program: &str) -> Self {
+        self.rebuild_program = Some(program.to_string());
+        self
+    }
+
+    /// Remplace l'identité Git par défaut (`Modulix-OS`) par `name <email>`,
+    /// utilisée comme auteur et committeur des commits de la transaction.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::GitError` – `name` ou `email` invalide (vide, `<`…).
+    #[allow(dead_code)]
+    pub fn with_signature(mut self, name: &str, email: &str) -> mx::Result<Self> {
+        self.git_user = git2::Signature::now(name, email).map_err(mx::ErrorKind::GitError)?;
+        Ok(self)
+    }
+
+    /// Si `force` est vrai, [`commit`] écrit les fichiers même s'ils ont été
+    /// modifiés sur disque depuis le `begin` : les modifications externes sont
+    /// écrasées au lieu de faire échouer la transaction.
+    #[allow(dead_code)]
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Remplace les verrous de build par défaut (`/tmp/mx-*.lock`).
+    #[allow(dead_code)]
+    pub fn with_lock_paths(mut self, lock_paths: LockPaths) -> Self {
+        self.lock_paths = lock_paths;
+        self
+    }
+
     /// Lance la reconstruction NixOS en sous-processus et attend sa fin.
     ///
-    /// Selon la variante de `build_command` :
+    /// Selon la variante de `build_type` :
     /// * [`BuildCommand::Install`] → `nixos-install --root /mnt --no-root-password --flake …`
-    /// * [`BuildCommand::Switch`] / [`BuildCommand::Boot`] → `nixos-rebuild <cmd> --flake …`
+    /// * les autres variantes → `nixos-rebuild <cmd> --flake …`
     ///
-    /// La sortie standard est héritée (visible dans le terminal parent) ; la sortie
-    /// d'erreur est capturée dans `stderr` si fournie.
+    /// Les sorties standard et d'erreur sont capturées.
     ///
     /// # Retour
-    /// `Ok(true)` si le processus s'est terminé avec succès (code 0), `Ok(false)` sinon.
-    fn rebuild_config(
-        path_config: &str,
-        config_name: &str,
-        build_command: BuildCommand,
-        stderr: Option<&mut String>,
-    ) -> mx::Result<bool> {
-        let mut child = match build_command {
-            BuildCommand::Install => process::Command::new("nixos-install")
-                .arg("--root")
-                .arg("/mnt")
-                .arg("--no-root-password")
-                .arg("--flake")
-                .arg(format!("{}#{}", path_config, config_name))
-                .stdout(process::Stdio::inherit())
-                .stderr(process::Stdio::piped())
-                .spawn()
-                .map_err(mx::ErrorKind::IOError)?,
-            BuildCommand::Switch | BuildCommand::Boot => process::Command::new("nixos-rebuild")
-                .arg(build_command.as_str())
-                .arg("--flake")
-                .arg(format!("{}#{}", path_config, config_name))
-                .stdout(process::Stdio::inherit())
-                .stderr(process::Stdio::piped())
-                .spawn()
-                .map_err(mx::ErrorKind::IOError)?,
+    /// Un [`RebuildReport`] contenant les sorties si le processus s'est terminé
+    /// avec succès (code 0).
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::BuildFailed`        – Le processus a échoué ; contient ses sorties.
+    /// * `mx::ErrorKind::RebuildToolMissing` – Le programme de reconstruction est introuvable.
+    /// * `mx::ErrorKind::IOError`            – Impossible de lancer le processus.
+    fn rebuild_config(&self) -> mx::Result<RebuildReport> {
+        let flake = format!("{}#{}", self.git_repo_path, self.config_name);
+        let mut command = match self.build_type {
+            BuildCommand::Install => {
+                let mut command = process::Command::new(
+                    self.rebuild_program.as_deref().unwrap_or("nixos-install"),
+                );
+                command.args(["--root", "/mnt", "--no-root-password", "--flake", &flake]);
+                command
+            }
+            BuildCommand::Switch
+            | BuildCommand::Boot
+            | BuildCommand::Build
+            | BuildCommand::BuildVm
+            | BuildCommand::DryActivate
+            | BuildCommand::Test => {
+                let mut command = process::Command::new(
+                    self.rebuild_program.as_deref().unwrap_or("nixos-rebuild"),
+                );
+                command.args([self.build_type.as_str(), "--flake", &flake]);
+                command
+            }
         };
 
-        let stderr_output = {
-            let mut s = String::new();
-            if let Some(mut err) = child.stderr.take() {
-                use std::io::Read;
-                err.read_to_string(&mut s).map_err(mx::ErrorKind::IOError)?;
-            }
-            s
+        let output = command.output().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => mx::ErrorKind::RebuildToolMissing(
+                command.get_program().to_string_lossy().into_owned(),
+            ),
+            _ => mx::ErrorKind::IOError(e),
+        })?;
+        let report = RebuildReport {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
         };
-        let status = child.wait().map_err(mx::ErrorKind::IOError)?;
-        if let Some(s) = stderr {
-            *s = stderr_output;
+        if output.status.success() {
+            Ok(report)
+        } else {
+            Err(mx::ErrorKind::BuildFailed {
+                stdout: report.stdout,
+                stderr: report.stderr,
+            })
         }
-        Ok(status.success())
     }
 
     /// Vérifie si `flake.lock` a été modifié (suivi ou non suivi) dans le dépôt Git.
@@ -302,13 +526,15 @@ impl<'a> Transaction<'a> {
     /// * `author`      – Signature de l'auteur.
     /// * `committer`   – Signature du committeur.
     /// * `message`     – Message du commit.
+    ///
+    /// Retourne l'OID du commit créé.
     fn git_commit(
         &self,
         update_ref: Option<&str>,
         author: &git2::Signature<'_>,
         committer: &git2::Signature<'_>,
         message: &str,
-    ) -> mx::Result<()> {
+    ) -> mx::Result<git2::Oid> {
         let mut index = self
             .git_repo
             .as_ref()
@@ -347,8 +573,7 @@ impl<'a> Transaction<'a> {
             .as_ref()
             .unwrap()
             .commit(update_ref, author, committer, message, &tree, &parents)
-            .map_err(mx::ErrorKind::GitError)?;
-        Ok(())
+            .map_err(mx::ErrorKind::GitError)
     }
 
     /// Détermine si un fichier a été modifié depuis le commit `oid`.
@@ -415,6 +640,77 @@ impl<'a> Transaction<'a> {
         Ok(())
     }
 
+    /// Retourne le chemin du dépôt Git de configuration.
+    #[allow(dead_code)]
+    pub fn get_config_dir(&self) -> &str {
+        &self.git_repo_path
+    }
+
+    /// Retourne le nom de la `nixosConfiguration` reconstruite au commit.
+    #[allow(dead_code)]
+    pub fn get_config_name(&self) -> &str {
+        &self.config_name
+    }
+
+    /// Liste les fichiers modifiés ou non suivis du dépôt, avec les mêmes
+    /// options de statut que `begin`.
+    ///
+    /// Ne nécessite pas de transaction active : permet à une interface de
+    /// montrer à l'utilisateur ce qui sera stashé avant d'appeler `begin`.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::GitError` – Dépôt introuvable ou erreur Git.
+    #[allow(dead_code)]
+    pub fn working_tree_status(&self) -> mx::Result<Vec<String>> {
+        let repo = git2::Repository::open(&self.git_repo_path).map_err(mx::ErrorKind::GitError)?;
+        let mut opts = git2::StatusOptions::new();
+        opts.include_untracked(true).include_ignored(false);
+        let statuses = repo
+            .statuses(Some(&mut opts))
+            .map_err(mx::ErrorKind::GitError)?;
+        Ok(statuses
+            .iter()
+            .filter_map(|entry| entry.path().map(String::from))
+            .collect())
+    }
+
+    /// Calcule, sans rien écrire ni reconstruire, le diff de chaque fichier
+    /// modifié en mémoire depuis le `begin`. Permet à une interface de demander
+    /// confirmation avant [`commit`].
+    ///
+    /// Les fichiers inchangés sont omis ; le résultat est trié par chemin.
+    ///
+    /// # Erreurs
+    /// * `mx::ErrorKind::TransactionNotBegin` – `begin` n'a pas encore été appelé.
+    #[allow(dead_code)]
+    pub fn plan(&self) -> mx::Result<Vec<FileDiff>> {
+        if self.git_repo.is_none() {
+            return Err(mx::ErrorKind::TransactionNotBegin);
+        }
+        let mut plan = vec![];
+        for (path, nix_file) in self.list_file.iter() {
+            let diff = nix_file.diff()?;
+            if !diff.is_empty() {
+                plan.push(FileDiff {
+                    path: path.clone(),
+                    diff,
+                });
+            }
+        }
+        plan.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(plan)
+    }
+
+    /// Retourne l'OID du commit Git créé par le dernier [`commit`] réussi, pour
+    /// l'afficher ou le journaliser.
+    ///
+    /// `None` si aucun commit n'a été fait, si le dernier n'avait aucune
+    /// modification à valider, ou s'il a été annulé.
+    #[allow(dead_code)]
+    pub fn last_commit(&self) -> Option<git2::Oid> {
+        self.last_commit
+    }
+
     /// Indique si une transaction est actuellement active.
     #[allow(dead_code)]
     pub fn as_begin(&self) -> bool {
@@ -536,43 +832,74 @@ impl<'a> Transaction<'a> {
     /// Appelé en fin de [`commit_impl`] et de [`rollback`] pour remettre en place
     /// les modifications qui étaient présentes avant l'ouverture de la transaction.
     ///
-    /// En cas d'échec du `stash_pop` (conflit), l'erreur est propagée mais
-    /// `stash_oid` est quand même réinitialisé pour éviter une double tentative.
+    /// En cas d'échec du `stash_pop` (conflit), le stash est conservé dans le
+    /// dépôt pour ne pas perdre les modifications et `mx::ErrorKind::StashConflict`
+    /// est retourné avec son OID. `stash_oid` est quand même réinitialisé pour
+    /// éviter une double tentative.
     fn stash_restore(&mut self) -> mx::Result<()> {
-        if self.stash_oid.take().is_some() {
-            match self.git_repo.as_mut().unwrap().stash_pop(0, None) {
-                Ok(_) => (),
-                Err(_) => {
-                    self.git_repo
-                        .as_mut()
-                        .unwrap()
-                        .stash_drop(0)
-                        .map_err(mx::ErrorKind::GitError)?;
-                }
-            }
+        if let Some(stash_oid) = self.stash_oid.take() {
+            self.git_repo
+                .as_mut()
+                .unwrap()
+                .stash_pop(0, None)
+                .map_err(|_| mx::ErrorKind::StashConflict(stash_oid))?;
         }
         Ok(())
     }
 
+    /// Génère `flake.lock` s'il est absent, crée le commit Git puis lance la
+    /// reconstruction. Appelé en tenant le verrou de build.
+    ///
+    /// Retourne l'OID du commit créé, `None` avec [`BuildCommand::DryActivate`].
+    fn commit_and_rebuild(&self) -> mx::Result<Option<git2::Oid>> {
+        if !self.flake_lock_exists() {
+            process::Command::new("nix")
+                .args(["flake", "update"])
+                .current_dir(&self.git_repo_path)
+                .output()
+                .map_err(mx::ErrorKind::IOError)?;
+        }
+        // dry-activate n'applique rien : il n'y a rien à committer.
+        if let BuildCommand::DryActivate = self.build_type {
+            self.rebuild_config()?;
+            return Ok(None);
+        }
+        let oid = self.git_commit(Some("HEAD"), &self.git_user, &self.git_user, &self.info)?;
+        self.rebuild_config()?;
+        Ok(Some(oid))
+    }
+
     /// Implémentation interne du commit, séparée pour permettre au wrapper
     /// [`commit`] de déclencher un rollback automatique en cas d'échec.
     ///
     /// Étapes :
-    /// 1. Commit de chaque [`NixFile`] sur disque.
+    /// 1. Écriture de chaque [`NixFile`] sur disque ; les fichiers restent
+    ///    verrouillés pour qu'un rollback puisse réécrire leur contenu d'origine.
     /// 2. Détection des fichiers réellement modifiés (`git add` sélectif).
     /// 3. Si au moins un fichier a changé :
-    ///    a. Génère `flake.lock` si absent (`nix flake update`).
-    ///    b. Crée le commit Git.
-    ///    c. Tente d'acquérir le verrou de build ; si obtenu, lance `nixos-rebuild`.
-    /// 4. Ferme tous les [`NixFile`] et libère le dépôt Git.
-    fn commit_impl(&mut self) -> mx::Result<()> {
+    ///    a. Prend la file d'attente de build (`BuildInProgress` si occupée).
+    ///    b. Attend le verrou de build (au plus `lock_timeout`).
+    ///    c. Génère `flake.lock` si absent (`nix flake update`).
+    ///    d. Crée le commit Git et lance `nixos-rebuild`.
+    /// 4. Avec [`BuildCommand::DryActivate`], aucun commit n'est créé et la
+    ///    transaction se termine par un [`rollback`] : le dépôt et les fichiers
+    ///    retrouvent leur état d'origine.
+    /// 5. Libère tous les [`NixFile`] (flag immutable, déverrouillage) et le
+    ///    dépôt Git.
+    fn commit_impl(&mut self, lock_timeout: Duration) -> mx::Result<()> {
         if self.git_repo.is_none() {
             return Err(mx::ErrorKind::TransactionNotBegin);
         }
+        self.last_commit = None;
         for (_, nix_file) in self.list_file.iter_mut() {
-            nix_file.commit()?;
+            if self.force {
+                nix_file.force_write()?;
+            } else {
+                nix_file.write()?;
+            }
         }
 
+        let mut new_commit = None;
         let mut need_modif = false;
         for (path, _) in self.list_file.iter() {
             if Self::has_diff_with_commit(self.git_repo.as_ref().unwrap(), self.old_commit, path)? {
@@ -582,51 +909,59 @@ impl<'a> Transaction<'a> {
         }
 
         if need_modif {
-            // Génère flake.lock s'il n'existe pas encore
-            if !self.flake_lock_exists() {
-                process::Command::new("nix")
-                    .args(["flake", "update"])
-                    .current_dir(&self.git_repo_path)
-                    .output()
-                    .map_err(mx::ErrorKind::IOError)?;
-            }
-            self.git_commit(Some("HEAD"), &self.git_user, &self.git_user, &self.info)?;
-
-            // Sérialisation du build : on n'entre dans la zone critique que si
-            // personne d'autre n'attend déjà (try_lock sur la file d'attente)
-            let mut queue = LockFile::try_lock(LOCK_QUEUE_BUILD_FILE)?;
-            if queue.is_some() {
-                let mut lock_build = LockFile::lock(LOCK_BUILD_FILE)?;
-                queue.as_mut().unwrap().unlock();
-                let mut stderr = String::new();
-                let success = Self::rebuild_config(
-                    &self.git_repo_path,
-                    CONFIG_NAME,
-                    self.build_type.clone(),
-                    Some(&mut stderr),
-                )?;
-                lock_build.unlock();
-                if !success {
-                    return Err(mx::ErrorKind::BuildError(stderr));
-                }
-            }
+            // Sérialisation du build, avant tout commit Git : on n'entre dans la
+            // zone critique que si personne d'autre n'attend déjà (try_lock sur
+            // la file d'attente). Sinon l'erreur déclenche le rollback du wrapper
+            // et le dépôt reste dans son état d'origine.
+            let mut queue = LockFile::try_lock(&self.lock_paths.queue)?
+                .ok_or(mx::ErrorKind::BuildInProgress)?;
+            let mut lock_build = LockFile::lock_timeout(&self.lock_paths.build, lock_timeout)?;
+            queue.unlock();
+
+            let result = self.commit_and_rebuild();
+            lock_build.unlock();
+            new_commit = result?;
+        }
+
+        if let BuildCommand::DryActivate = self.build_type {
+            return self.rollback();
         }
 
         for (_, nix_file) in self.list_file.iter_mut() {
-            nix_file.close()?;
+            nix_file.release()?;
         }
-        // Restaure les modifications stashées avant la transaction
-        self.stash_restore()?;
+        self.last_commit = new_commit;
+        // Restaure les modifications stashées avant la transaction. La
+        // transaction est terminée même en cas de conflit, pour que le
+        // rollback du wrapper n'annule pas le commit déjà créé.
+        let restored = self.stash_restore();
         self.git_repo = None;
-        Ok(())
+        restored
     }
     /// persiste les modifications, crée un commit Git
     /// et déclenche la reconstruction NixOS.
     ///
     /// En cas d'échec interne, un [`rollback`] automatique est tenté avant de
-    /// propager l'erreur.
+    /// propager l'erreur. Seule exception : `mx::ErrorKind::StashConflict`, retourné
+    /// quand le commit est fait mais que les modifications stashées au `begin` ne
+    /// peuvent pas être réappliquées ; elles restent alors dans le stash.
+    ///
+    /// Si une autre transaction attend déjà le verrou de build, retourne
+    /// `mx::ErrorKind::BuildInProgress` : rien n'est committé et les fichiers
+    /// sont restaurés, la transaction peut être rejouée plus tard.
     pub fn commit(&mut self) -> mx::Result<()> {
-        self.commit_impl().map_err(|e| {
+        self.commit_with_timeout(Duration::MAX)
+    }
+
+    /// Identique à [`commit`], mais n'attend le verrou de build que pendant
+    /// `lock_timeout` au plus.
+    ///
+    /// # Erreurs
+    /// `mx::ErrorKind::FailToLock` si le verrou de build est toujours tenu par
+    /// une autre transaction après `lock_timeout` ; la transaction est alors
+    /// annulée comme pour tout autre échec.
+    pub fn commit_with_timeout(&mut self, lock_timeout: Duration) -> mx::Result<()> {
+        self.commit_impl(lock_timeout).map_err(|e| {
             let _ = self.rollback();
             e
         })
@@ -635,6 +970,8 @@ impl<'a> Transaction<'a> {
     /// Annule la transaction et restaure l'état précédent du dépôt Git.
     ///
     /// Étapes :
+    /// 0. Réécrit le contenu d'origine des fichiers encore verrouillés (écrits
+    ///    par un `commit` en échec), avant de relâcher quoi que ce soit.
     /// 1. Si le dépôt était vide au `begin` (`old_commit` zéro) : ferme les fichiers
     ///    et sort sans toucher à Git.
     /// 2. Sinon : repointe la branche courante sur `old_commit` et effectue un
@@ -643,12 +980,18 @@ impl<'a> Transaction<'a> {
     ///    immutable sur les fichiers préexistants restaurés.
     ///
     /// # Erreurs
-    /// `mx::ErrorKind::TransactionNotBegin` si aucune transaction n'est active.
+    /// * `mx::ErrorKind::TransactionNotBegin` – Aucune transaction active.
+    /// * `mx::ErrorKind::StashConflict`       – Les modifications stashées au `begin`
+    ///   ne peuvent pas être réappliquées ; elles restent dans le stash.
     pub fn rollback(&mut self) -> mx::Result<()> {
         if self.git_repo.is_none() {
             return Err(mx::ErrorKind::TransactionNotBegin);
         }
 
+        for (_, nix_file) in self.list_file.iter_mut() {
+            let _ = nix_file.write_original();
+        }
+
         {
             // Cas particulier : dépôt vide, aucun commit à restaurer
             if self.old_commit.is_zero() {
@@ -707,9 +1050,9 @@ impl<'a> Transaction<'a> {
             }
         }
         // Restaure les modifications stashées avant la transaction
-        self.stash_restore()?;
+        let restored = self.stash_restore();
         self.git_repo = None;
-        Ok(())
+        restored
     }
 }
 
@@ -5,6 +5,8 @@
 /// - `integration`    – Real temporary Git repository.
 /// - `no_regression` – Edge cases and historical bugs.
 /// - `stash`          – Auto-stash behaviour when the repo is dirty on `begin`.
+/// - `rebuild`        – Output capture of the rebuild command, using stub programs.
+/// - `locks`          – Build serialisation through configurable lock paths.
 ///
 /// # Path convention
 /// `NixFile::new(repo_path, relative_path)` concatenates directly:
@@ -20,7 +22,7 @@
 /// [dev-dependencies]
 /// tempfile = "3"
 /// ```
-use super::{BuildCommand, Transaction};
+use super::{BuildCommand, LockFile, LockPaths, Transaction};
 use crate::mx;
 use std::fs;
 use tempfile::TempDir;
@@ -52,6 +54,17 @@ fn setup_repo() -> (TempDir, git2::Repository) {
     (dir, repo)
 }
 
+/// Writes an executable shell script named `name` in `dir` and returns its path.
+/// Used in place of `nixos-rebuild` so the rebuild step can run in tests.
+fn stub_program(dir: &TempDir, name: &str, script: &str) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.path().join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
 /// Stages and commits every file currently in the working tree.
 fn commit_all(repo: &git2::Repository, message: &str) {
     let sig = git2::Signature::now("Test", "test@test.com").unwrap();
@@ -95,6 +108,24 @@ mod unit {
         assert!(Transaction::new("", "", BuildCommand::Install).is_ok());
     }
 
+    /// `new` targets the default configuration name.
+    #[test]
+    fn new_uses_default_config_name() {
+        let t = Transaction::new("/some/path/", "desc", BuildCommand::Install).unwrap();
+        assert_eq!(t.get_config_dir(), "/some/path/");
+        assert_eq!(t.get_config_name(), crate::CONFIG_NAME);
+    }
+
+    /// `with_config` keeps the given directory and configuration name.
+    #[test]
+    fn with_config_sets_dir_and_name() {
+        let t = Transaction::with_config("/home/me/nixos/", "desc", BuildCommand::Boot, "laptop")
+            .unwrap();
+        assert_eq!(t.get_config_dir(), "/home/me/nixos/");
+        assert_eq!(t.get_config_name(), "laptop");
+        assert!(!t.as_begin());
+    }
+
     /// `add_file` succeeds before `begin`.
     #[test]
     fn add_file_before_begin_ok() {
@@ -138,21 +169,14 @@ mod unit {
         assert!(t.commit().is_err());
     }
 
-    /// In debug mode all `BuildCommand` variants return `"build-vm"`.
+    /// Each variant maps to its `nixos-rebuild` subcommand, whatever the
+    /// build profile.
     #[test]
-    #[cfg(debug_assertions)]
-    fn build_command_debug_all_return_build_vm() {
-        assert_eq!(BuildCommand::Switch.as_str(), "build-vm");
-        assert_eq!(BuildCommand::Boot.as_str(), "build-vm");
-        assert_eq!(BuildCommand::Install.as_str(), "build-vm");
-    }
-
-    /// In release mode each variant returns its expected string.
-    #[test]
-    #[cfg(not(debug_assertions))]
-    fn build_command_release_correct_values() {
+    fn build_command_as_str() {
         assert_eq!(BuildCommand::Switch.as_str(), "switch");
         assert_eq!(BuildCommand::Boot.as_str(), "boot");
+        assert_eq!(BuildCommand::Build.as_str(), "build");
+        assert_eq!(BuildCommand::BuildVm.as_str(), "build-vm");
         assert_eq!(BuildCommand::Install.as_str(), "");
     }
 
@@ -183,6 +207,19 @@ mod integration {
         t.rollback().unwrap();
     }
 
+    /// A transaction built with `with_config` works on any repository path.
+    #[test]
+    fn with_config_begin_and_rollback() {
+        let (dir, _repo) = setup_repo();
+        let mut t =
+            Transaction::with_config(&repo_path(&dir), "desc", BuildCommand::Install, "laptop")
+                .unwrap();
+        t.begin().unwrap();
+        assert!(t.get_file("configuration.nix").is_ok());
+        t.rollback().unwrap();
+        assert!(!t.as_begin());
+    }
+
     /// `begin` fails when the directory is not a Git repository.
     #[test]
     fn begin_not_a_git_repo_errors() {
@@ -274,6 +311,46 @@ mod integration {
         assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
     }
 
+    // ── plan ──────────────────────────────────────────────────────────────────
+
+    /// `plan` reports the added line of each edited file, and writes nothing.
+    #[test]
+    fn plan_reports_added_line() {
+        let (dir, repo) = setup_repo();
+        fs::write(dir.path().join("other.nix"), "{\n}\n").unwrap();
+        commit_all(&repo, "other.nix");
+        let config_path = dir.path().join("configuration.nix");
+        let original = fs::read_to_string(&config_path).unwrap();
+
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Install).unwrap();
+        t.add_file("other.nix").unwrap();
+        t.begin().unwrap();
+        assert!(t.plan().unwrap().is_empty());
+        t.get_file("configuration.nix")
+            .unwrap()
+            .set_option("networking.hostName", "\"host\"")
+            .unwrap();
+
+        let plan = t.plan().unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].path, "configuration.nix");
+        assert!(
+            plan[0]
+                .diff
+                .lines()
+                .any(|l| l == "+    hostName = \"host\";")
+        );
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
+        t.rollback().unwrap();
+    }
+
+    /// `plan` requires an active transaction.
+    #[test]
+    fn plan_without_begin_errors() {
+        let t = Transaction::new("/some/path/", "desc", BuildCommand::Install).unwrap();
+        assert!(matches!(t.plan(), Err(mx::ErrorKind::TransactionNotBegin)));
+    }
+
     // ── commit ────────────────────────────────────────────────────────────────
 
     /// A commit with no diff does not create a new Git commit.
@@ -290,6 +367,7 @@ mod integration {
             repo.head().unwrap().peel_to_commit().unwrap().id(),
             commit_before
         );
+        assert_eq!(t.last_commit(), None);
     }
 
     /// After `commit`, the transaction is closed.
@@ -513,6 +591,17 @@ mod stash {
         t.rollback().unwrap();
     }
 
+    /// `working_tree_status` lists the files `begin` would stash.
+    #[test]
+    fn working_tree_status_lists_dirty_files() {
+        let (dir, _repo) = setup_repo();
+        let t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Install).unwrap();
+        assert!(t.working_tree_status().unwrap().is_empty());
+
+        fs::write(dir.path().join("untracked.nix"), "untracked content").unwrap();
+        assert_eq!(t.working_tree_status().unwrap(), vec!["untracked.nix"]);
+    }
+
     /// After `rollback`, stashed files are restored to the working tree.
     #[test]
     fn rollback_restores_stash() {
@@ -563,6 +652,43 @@ mod stash {
         );
     }
 
+    /// A stash conflicting with the committed change is kept and reported as
+    /// `StashConflict`, while the commit itself is not rolled back.
+    #[test]
+    fn commit_keeps_conflicting_stash() {
+        let (dir, mut repo) = setup_repo();
+        let program = stub_program(&dir, "rebuild", "exit 0");
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+        let config_path = dir.path().join("configuration.nix");
+        fs::write(&config_path, "{ stashed = true; }\n").unwrap();
+
+        let lock_dir = TempDir::new().unwrap();
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(LockPaths::in_dir(lock_dir.path().to_str().unwrap()));
+        t.begin().unwrap();
+        *t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap() = String::from("{ committed = true; }\n");
+
+        assert!(matches!(t.commit(), Err(mx::ErrorKind::StashConflict(_))));
+        assert!(!t.as_begin());
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "{ committed = true; }\n"
+        );
+        let mut stashes = 0;
+        repo.stash_foreach(|_, _, _| {
+            stashes += 1;
+            true
+        })
+        .unwrap();
+        assert_eq!(stashes, 1, "conflicting stash must not be dropped");
+    }
+
     /// `begin` on a dirty repo with staged changes stashes them correctly.
     #[test]
     fn begin_stashes_staged_changes() {
@@ -610,3 +736,458 @@ mod stash {
         t2.rollback().unwrap();
     }
 }
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Rebuild – captured output of the rebuild command
+// ─────────────────────────────────────────────────────────────────────────────
+mod rebuild {
+    use super::*;
+
+    /// A failing rebuild returns `BuildFailed` with both captured outputs.
+    #[test]
+    fn failing_rebuild_propagates_output() {
+        let dir = TempDir::new().unwrap();
+        let program = stub_program(
+            &dir,
+            "rebuild",
+            "echo building; echo 'error: boom' >&2; exit 1",
+        );
+        let t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program(&program);
+
+        match t.rebuild_config() {
+            Err(mx::ErrorKind::BuildFailed { stdout, stderr }) => {
+                assert_eq!(stdout, "building\n");
+                assert_eq!(stderr, "error: boom\n");
+            }
+            other => panic!("expected BuildFailed, got {:?}", other),
+        }
+    }
+
+    /// A successful rebuild returns its captured output.
+    #[test]
+    fn successful_rebuild_returns_report() {
+        let dir = TempDir::new().unwrap();
+        let program = stub_program(&dir, "rebuild", "echo \"$@\"");
+        let t = Transaction::with_config(&repo_path(&dir), "desc", BuildCommand::Boot, "laptop")
+            .unwrap()
+            .with_rebuild_program(&program);
+
+        let report = t.rebuild_config().unwrap();
+        assert_eq!(
+            report.stdout,
+            format!(
+                "{} --flake {}#laptop\n",
+                BuildCommand::Boot.as_str(),
+                repo_path(&dir)
+            )
+        );
+        assert!(report.stderr.is_empty());
+    }
+
+    /// `DryActivate` and `Test` pass their own subcommand to the rebuild program.
+    #[test]
+    fn dry_activate_and_test_arguments() {
+        let dir = TempDir::new().unwrap();
+        let program = stub_program(&dir, "rebuild", "echo \"$@\"");
+        for (build, subcommand) in [
+            (BuildCommand::DryActivate, "dry-activate"),
+            (BuildCommand::Test, "test"),
+        ] {
+            let t = Transaction::new(&repo_path(&dir), "desc", build)
+                .unwrap()
+                .with_rebuild_program(&program);
+            assert_eq!(
+                t.rebuild_config().unwrap().stdout,
+                format!(
+                    "{} --flake {}#{}\n",
+                    subcommand,
+                    repo_path(&dir),
+                    crate::CONFIG_NAME
+                )
+            );
+        }
+    }
+
+    /// A dry activation runs the rebuild on the edited files, then leaves HEAD
+    /// and the files as they were, without any commit.
+    #[test]
+    fn dry_activate_does_not_commit() {
+        let (dir, repo) = setup_repo();
+        let config_path = dir.path().join("configuration.nix");
+        let seen = dir.path().join("seen.nix");
+        let program = stub_program(
+            &dir,
+            "rebuild",
+            &format!(
+                "cp {} {}",
+                config_path.to_str().unwrap(),
+                seen.to_str().unwrap()
+            ),
+        );
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+        let before = repo.head().unwrap().peel_to_commit().unwrap().id();
+        let original = fs::read_to_string(&config_path).unwrap();
+
+        let lock_dir = TempDir::new().unwrap();
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::DryActivate)
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(LockPaths::in_dir(lock_dir.path().to_str().unwrap()));
+        t.begin().unwrap();
+        t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap()
+            .push_str("# change\n");
+        t.commit().unwrap();
+
+        assert_eq!(t.last_commit(), None);
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), before);
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
+        assert!(fs::read_to_string(&seen).unwrap().ends_with("# change\n"));
+    }
+
+    /// A missing rebuild program is reported as `RebuildToolMissing` with its
+    /// name, not as a failed build.
+    #[test]
+    fn missing_rebuild_program_errors() {
+        let t = Transaction::new("/some/path/", "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program("/nonexistent/nixos-rebuild");
+        match t.rebuild_config() {
+            Err(mx::ErrorKind::RebuildToolMissing(program)) => {
+                assert_eq!(program, "/nonexistent/nixos-rebuild")
+            }
+            other => panic!("expected RebuildToolMissing, got {:?}", other),
+        }
+    }
+
+    /// On a failing rebuild, the edited files are still locked while the
+    /// rebuild runs, then restored to their original content with HEAD.
+    #[test]
+    fn failing_rebuild_restores_files() {
+        let (dir, repo) = setup_repo();
+        let config_path = dir.path().join("configuration.nix");
+        let program = stub_program(
+            &dir,
+            "rebuild",
+            &format!(
+                "flock -n {} true && echo unlocked; exit 1",
+                config_path.to_str().unwrap()
+            ),
+        );
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+        let original = fs::read_to_string(&config_path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap().id();
+
+        let lock_dir = TempDir::new().unwrap();
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(LockPaths::in_dir(lock_dir.path().to_str().unwrap()));
+        t.begin().unwrap();
+        t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap()
+            .push_str("# change\n");
+
+        match t.commit() {
+            Err(mx::ErrorKind::BuildFailed { stdout, .. }) => {
+                assert!(stdout.is_empty(), "file unlocked during rebuild");
+            }
+            other => panic!("expected BuildFailed, got {:?}", other),
+        }
+        assert!(!t.as_begin());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), head);
+    }
+
+    /// A file modified on disk since `begin` makes `commit` fail, unless the
+    /// transaction was built `with_force`.
+    #[test]
+    fn with_force_overwrites_external_change() {
+        let (dir, repo) = setup_repo();
+        let config_path = dir.path().join("configuration.nix");
+        let program = stub_program(&dir, "rebuild", "exit 0");
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+
+        let lock_dir = TempDir::new().unwrap();
+        let lock_paths = LockPaths::in_dir(lock_dir.path().to_str().unwrap());
+        for force in [false, true] {
+            let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+                .unwrap()
+                .with_rebuild_program(&program)
+                .with_lock_paths(lock_paths.clone())
+                .with_force(force);
+            t.begin().unwrap();
+            t.get_file("configuration.nix")
+                .unwrap()
+                .get_mut_file_content()
+                .unwrap()
+                .push_str("# change\n");
+            fs::write(&config_path, "{ }\n").unwrap();
+
+            let result = t.commit();
+            if force {
+                result.unwrap();
+                assert!(
+                    fs::read_to_string(&config_path)
+                        .unwrap()
+                        .ends_with("# change\n")
+                );
+            } else {
+                assert!(matches!(result, Err(mx::ErrorKind::FileChangedExternally)));
+                assert!(!t.as_begin());
+            }
+        }
+    }
+
+    /// After a successful commit, `last_commit` is the new HEAD commit, whose
+    /// parent is the commit the transaction started from.
+    #[test]
+    fn last_commit_is_new_head() {
+        let (dir, repo) = setup_repo();
+        let program = stub_program(&dir, "rebuild", "exit 0");
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+        let before = repo.head().unwrap().peel_to_commit().unwrap().id();
+
+        let lock_dir = TempDir::new().unwrap();
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(LockPaths::in_dir(lock_dir.path().to_str().unwrap()));
+        assert_eq!(t.last_commit(), None);
+        t.begin().unwrap();
+        t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap()
+            .push_str("# change\n");
+        t.commit().unwrap();
+
+        let oid = t.last_commit().expect("a commit was created");
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id(), oid);
+        assert_eq!(head.parent_id(0).unwrap(), before);
+        assert_eq!(head.message(), Some("desc"));
+    }
+
+    /// Commits are attributed to the identity given to `with_signature`.
+    #[test]
+    fn with_signature_sets_commit_author() {
+        let (dir, repo) = setup_repo();
+        let program = stub_program(&dir, "rebuild", "exit 0");
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+
+        let lock_dir = TempDir::new().unwrap();
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_signature("Alice Admin", "alice@example.org")
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(LockPaths::in_dir(lock_dir.path().to_str().unwrap()));
+        t.begin().unwrap();
+        t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap()
+            .push_str("# change\n");
+        t.commit().unwrap();
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        for signature in [head.author(), head.committer()] {
+            assert_eq!(signature.name(), Some("Alice Admin"));
+            assert_eq!(signature.email(), Some("alice@example.org"));
+        }
+    }
+
+    /// An invalid identity is rejected.
+    #[test]
+    fn with_signature_rejects_invalid_identity() {
+        let t = Transaction::new("/some/path/", "desc", BuildCommand::Switch).unwrap();
+        assert!(matches!(
+            t.with_signature("", "alice@example.org"),
+            Err(mx::ErrorKind::GitError(_))
+        ));
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Locks – configurable build lock paths
+// ─────────────────────────────────────────────────────────────────────────────
+mod locks {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Default lock paths are the historical `/tmp` ones.
+    #[test]
+    fn default_lock_paths() {
+        let paths = LockPaths::default();
+        assert_eq!(paths.build, "/tmp/mx-build.lock");
+        assert_eq!(paths.queue, "/tmp/mx-queue-build.lock");
+    }
+
+    /// The PID of the process holding a lock is recorded in the lock file,
+    /// then cleared on unlock.
+    #[test]
+    fn holder_is_recorded_then_cleared() {
+        let lock_dir = TempDir::new().unwrap();
+        let paths = LockPaths::in_dir(lock_dir.path().to_str().unwrap());
+        assert_eq!(paths.build_holder(), None);
+
+        let mut lock = LockFile::lock(&paths.build).unwrap();
+        assert_eq!(paths.build_holder(), Some(std::process::id()));
+        assert_eq!(LockFile::try_lock(&paths.build).unwrap().map(|_| ()), None);
+        assert_eq!(LockFile::holder(&paths.build), Some(std::process::id()));
+
+        lock.unlock();
+        assert_eq!(paths.build_holder(), None);
+        assert_eq!(fs::read_to_string(&paths.build).unwrap(), "");
+    }
+
+    /// Commits a change in a fresh repository using `lock_paths`, and returns
+    /// whether the stub rebuild ran.
+    fn commit_with_locks(lock_paths: LockPaths) -> bool {
+        let (dir, repo) = setup_repo();
+        let marker = dir.path().join("rebuilt");
+        let program = stub_program(
+            &dir,
+            "rebuild",
+            &format!("sleep 0.2; touch {}", marker.to_str().unwrap()),
+        );
+        // Committed so that `begin` does not stash them; `flake.lock` also
+        // avoids running `nix flake update`.
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(lock_paths);
+        t.begin().unwrap();
+        t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap()
+            .push_str("# change\n");
+        t.commit().unwrap();
+        marker.exists()
+    }
+
+    /// Two transactions with distinct lock paths build concurrently, even
+    /// while the build lock of another set of paths is held.
+    #[test]
+    fn distinct_lock_paths_do_not_block() {
+        let held_dir = TempDir::new().unwrap();
+        let held = LockPaths::in_dir(held_dir.path().to_str().unwrap());
+        let held_lock = fs::File::create(&held.build).unwrap();
+        held_lock.lock().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let lock_dirs: Vec<TempDir> = (0..2).map(|_| TempDir::new().unwrap()).collect();
+        for lock_dir in &lock_dirs {
+            let tx = tx.clone();
+            let paths = LockPaths::in_dir(lock_dir.path().to_str().unwrap());
+            thread::spawn(move || tx.send(commit_with_locks(paths)).unwrap());
+        }
+
+        for _ in 0..2 {
+            let rebuilt = rx
+                .recv_timeout(Duration::from_secs(30))
+                .expect("transaction blocked on another lock");
+            assert!(rebuilt);
+        }
+        held_lock.unlock().unwrap();
+    }
+
+    /// `commit_with_timeout` gives up with `FailToLock` while another
+    /// transaction holds the build lock, rolls the transaction back and
+    /// leaves the queue free.
+    #[test]
+    fn commit_with_timeout_fails_while_build_lock_held() {
+        let (dir, repo) = setup_repo();
+        let program = stub_program(&dir, "rebuild", "exit 0");
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+        let config_path = dir.path().join("configuration.nix");
+        let original = fs::read_to_string(&config_path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap().id();
+
+        let lock_dir = TempDir::new().unwrap();
+        let paths = LockPaths::in_dir(lock_dir.path().to_str().unwrap());
+        let held_lock = fs::File::create(&paths.build).unwrap();
+        held_lock.lock().unwrap();
+
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(paths.clone());
+        t.begin().unwrap();
+        t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap()
+            .push_str("# change\n");
+
+        let start = std::time::Instant::now();
+        let result = t.commit_with_timeout(Duration::from_millis(200));
+        assert!(matches!(result, Err(mx::ErrorKind::FailToLock)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!t.as_begin());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), head);
+        assert_eq!(LockFile::holder(&paths.queue), None);
+        held_lock.unlock().unwrap();
+    }
+
+    /// While another transaction is queued, `commit` fails with
+    /// `BuildInProgress` without creating a Git commit, and restores the files.
+    #[test]
+    fn commit_while_queued_returns_build_in_progress() {
+        let (dir, repo) = setup_repo();
+        let marker = dir.path().join("rebuilt");
+        let program = stub_program(
+            &dir,
+            "rebuild",
+            &format!("touch {}", marker.to_str().unwrap()),
+        );
+        fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+        commit_all(&repo, "flake.lock and stub");
+        let config_path = dir.path().join("configuration.nix");
+        let original = fs::read_to_string(&config_path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap().id();
+
+        let lock_dir = TempDir::new().unwrap();
+        let paths = LockPaths::in_dir(lock_dir.path().to_str().unwrap());
+        let queued = fs::File::create(&paths.queue).unwrap();
+        queued.lock().unwrap();
+
+        let mut t = Transaction::new(&repo_path(&dir), "desc", BuildCommand::Switch)
+            .unwrap()
+            .with_rebuild_program(&program)
+            .with_lock_paths(paths);
+        t.begin().unwrap();
+        t.get_file("configuration.nix")
+            .unwrap()
+            .get_mut_file_content()
+            .unwrap()
+            .push_str("# change\n");
+
+        assert!(matches!(t.commit(), Err(mx::ErrorKind::BuildInProgress)));
+        assert!(!t.as_begin());
+        assert!(!marker.exists());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), original);
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), head);
+        queued.unlock().unwrap();
+    }
+}
@@ -1,9 +1,40 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use rnix::SyntaxKind;
+use rnix::ast::{AttrSet, Expr, HasEntry, InterpolPart};
+use rowan::ast::AstNode;
+
+use crate::core::IndentStyle;
 use crate::mx;
 
 pub fn value_to_string_nix(value: &str) -> String {
     String::from("\"") + value + "\""
 }
 
+/// Quotes `value` as a `"` Nix string, escaping `"`, `\` and `${`.
+pub fn escape_string_nix(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Escapes `''` and `${` in a line of a `''` Nix string.
+pub fn escape_indented_string_nix(line: &str) -> String {
+    line.replace("''", "'''").replace("${", "''${")
+}
+
 pub fn value_to_block_string_nix(value: &str) -> String {
     String::from("'''") + value + "'''"
 }
@@ -20,3 +51,447 @@ pub fn string_nix_to_value(str_nix: &str) -> mx::Result<&str> {
         },
     }
 }
+
+/// Returns `true` if `value` is a Nix path literal (`/abs`, `./rel`, `~/home`
+/// or `<search>`), or a string without interpolation holding an absolute,
+/// relative or home path.
+pub fn is_valid_path_value(value: &str) -> bool {
+    let root = rnix::Root::parse(value);
+    if !root.errors().is_empty() {
+        return false;
+    }
+    match root.tree().expr() {
+        Some(Expr::PathAbs(_) | Expr::PathRel(_) | Expr::PathHome(_) | Expr::PathSearch(_)) => true,
+        Some(Expr::Str(string)) => match string.normalized_parts().as_slice() {
+            [InterpolPart::Literal(path)] => ["/", "./", "../", "~/"]
+                .iter()
+                .any(|prefix| path.starts_with(prefix)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns the indentation unit used by the majority of the lines of `file_content`,
+/// or the default style when nothing is indented.
+pub fn detect_indent(file_content: &str) -> IndentStyle {
+    let mut tab_lines = 0usize;
+    let mut space_lines = 0usize;
+    let mut steps: HashMap<usize, usize> = HashMap::new();
+    let mut previous = 0usize;
+
+    for line in file_content.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with('\t') {
+            tab_lines += 1;
+            continue;
+        }
+        let width = line.len() - line.trim_start_matches(' ').len();
+        if width > 0 {
+            space_lines += 1;
+        }
+        if width > previous {
+            *steps.entry(width - previous).or_default() += 1;
+        }
+        previous = width;
+    }
+
+    if tab_lines > space_lines {
+        return IndentStyle::Tabs;
+    }
+    steps
+        .into_iter()
+        .max_by_key(|&(width, count)| (count, Reverse(width)))
+        .map_or(IndentStyle::default(), |(width, _)| {
+            IndentStyle::Spaces(width)
+        })
+}
+
+/// Re-indents `file_content` with `style` and puts each entry of a multi-line
+/// attribute set on its own line. The content of multi-line strings and
+/// comments is kept as is. Fails on a file with syntax errors.
+#[allow(dead_code)]
+pub fn format_file(file_content: &str, style: IndentStyle) -> mx::Result<String> {
+    let split = split_entries(file_content)?;
+    let root = rnix::Root::parse(&split);
+    if !root.errors().is_empty() {
+        return Err(mx::ErrorKind::InvalidFile);
+    }
+
+    // Ranges whose inner lines must not be touched, and the offset of every
+    // token opening or closing an indentation level.
+    let mut verbatim: Vec<Range<usize>> = vec![];
+    let mut events: Vec<(usize, bool)> = vec![];
+    for element in root.syntax().descendants_with_tokens() {
+        let range: Range<usize> =
+            element.text_range().start().into()..element.text_range().end().into();
+        match element.kind() {
+            SyntaxKind::NODE_STRING | SyntaxKind::TOKEN_COMMENT => verbatim.push(range),
+            SyntaxKind::TOKEN_L_BRACE
+            | SyntaxKind::TOKEN_L_BRACK
+            | SyntaxKind::TOKEN_L_PAREN
+            | SyntaxKind::TOKEN_INTERPOL_START
+            | SyntaxKind::TOKEN_LET => events.push((range.start, true)),
+            SyntaxKind::TOKEN_R_BRACE
+            | SyntaxKind::TOKEN_R_BRACK
+            | SyntaxKind::TOKEN_R_PAREN
+            | SyntaxKind::TOKEN_INTERPOL_END
+            | SyntaxKind::TOKEN_IN => events.push((range.start, false)),
+            _ => (),
+        }
+    }
+
+    // Each line opening one or more levels indents the following lines by
+    // one, a closing token goes back to the level of the line that opened it.
+    let mut levels: Vec<usize> = vec![];
+    let mut events = events.into_iter().peekable();
+    let mut formatted = String::with_capacity(split.len());
+    let mut line_start = 0;
+    for line in split.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        let content = line.trim();
+        let content_start = line_start + (line.len() - line.trim_start().len());
+        let closes_first = events
+            .peek()
+            .is_some_and(|&(offset, open)| offset == content_start && !open);
+        let level = match levels.last() {
+            Some(&level) if closes_first => level - 1,
+            Some(&level) => level,
+            None => 0,
+        };
+
+        if verbatim
+            .iter()
+            .any(|range| range.start < line_start && line_start < range.end)
+        {
+            formatted.push_str(line);
+        } else if content.is_empty() {
+            formatted.push_str(&line[line.trim_end_matches(['\r', '\n']).len()..]);
+        } else {
+            formatted.push_str(&style.repeat(level));
+            formatted.push_str(content);
+            formatted.push_str(&line[line.trim_end_matches(['\r', '\n']).len()..]);
+        }
+
+        while let Some(&(offset, open)) = events.peek() {
+            if offset >= line_end {
+                break;
+            }
+            events.next();
+            if open {
+                levels.push(level + 1);
+            } else {
+                levels.pop();
+            }
+        }
+        line_start = line_end;
+    }
+    Ok(formatted)
+}
+
+/// Inserts a newline before every entry, and before the closing brace, of a
+/// multi-line attribute set that shares its line with the previous entry.
+fn split_entries(file_content: &str) -> mx::Result<String> {
+    let root = rnix::Root::parse(file_content);
+    if !root.errors().is_empty() {
+        return Err(mx::ErrorKind::InvalidFile);
+    }
+
+    let mut breaks: Vec<usize> = vec![];
+    for attr_set in root.syntax().descendants().filter_map(AttrSet::cast) {
+        let syntax = attr_set.syntax();
+        if !syntax.text().contains_char('\n') {
+            continue;
+        }
+        let closing = syntax
+            .last_token()
+            .filter(|token| token.kind() == SyntaxKind::TOKEN_R_BRACE);
+        let starts = attr_set
+            .entries()
+            .filter_map(|entry| entry.syntax().first_token())
+            .chain(closing);
+        for token in starts {
+            let mut previous = token.prev_token();
+            while let Some(ws) = previous
+                .as_ref()
+                .filter(|t| t.kind() == SyntaxKind::TOKEN_WHITESPACE)
+            {
+                if ws.text().contains('\n') {
+                    break;
+                }
+                previous = ws.prev_token();
+            }
+            if previous.is_some_and(|t| {
+                matches!(
+                    t.kind(),
+                    SyntaxKind::TOKEN_SEMICOLON | SyntaxKind::TOKEN_L_BRACE
+                )
+            }) {
+                breaks.push(token.text_range().start().into());
+            }
+        }
+    }
+
+    breaks.sort_unstable();
+    let mut split = String::with_capacity(file_content.len() + breaks.len());
+    let mut last = 0;
+    for pos in breaks {
+        split.push_str(file_content[last..pos].trim_end_matches([' ', '\t']));
+        split.push('\n');
+        last = pos;
+    }
+    split.push_str(&file_content[last..]);
+    Ok(split)
+}
+
+/// Line based unified diff between `old` and `new`, with 3 lines of context
+/// around each hunk. Returns an empty string when both are identical.
+pub fn line_diff(old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let (n, m) = (old_lines.len(), new_lines.len());
+
+    // Edits are local: the common prefix and suffix are kept out of the LCS
+    // table, which would otherwise be quadratic in the size of the file.
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_end, new_end) = (n - suffix, m - suffix);
+
+    // lcs[i][j] : longest common subsequence of old_lines[prefix + i..old_end]
+    // and new_lines[prefix + j..new_end]
+    let (rows, cols) = (old_end - prefix, new_end - prefix);
+    let mut lcs = vec![vec![0usize; cols + 1]; rows + 1];
+    for i in (0..rows).rev() {
+        for j in (0..cols).rev() {
+            lcs[i][j] = if old_lines[prefix + i] == new_lines[prefix + j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    // (tag, old index, new index, line)
+    let mut ops: Vec<(char, usize, usize, &str)> =
+        (0..prefix).map(|k| (' ', k, k, old_lines[k])).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < rows || j < cols {
+        let (oi, nj) = (prefix + i, prefix + j);
+        if i < rows && j < cols && old_lines[oi] == new_lines[nj] {
+            ops.push((' ', oi, nj, old_lines[oi]));
+            i += 1;
+            j += 1;
+        } else if i < rows && (j == cols || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', oi, nj, old_lines[oi]));
+            i += 1;
+        } else {
+            ops.push(('+', oi, nj, new_lines[nj]));
+            j += 1;
+        }
+    }
+    ops.extend((0..suffix).map(|k| (' ', old_end + k, new_end + k, old_lines[old_end + k])));
+
+    let mut diff = String::new();
+    let mut k = 0;
+    while k < ops.len() {
+        if ops[k].0 == ' ' {
+            k += 1;
+            continue;
+        }
+        let start = k.saturating_sub(CONTEXT);
+        let mut end = k;
+        let mut unchanged = 0;
+        while end < ops.len() && unchanged <= 2 * CONTEXT {
+            unchanged = if ops[end].0 == ' ' { unchanged + 1 } else { 0 };
+            end += 1;
+        }
+        let end = (end - unchanged + CONTEXT.min(unchanged)).min(ops.len());
+
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|op| op.0 != '+').count();
+        let new_count = hunk.iter().filter(|op| op.0 != '-').count();
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            hunk[0].1 + 1,
+            old_count,
+            hunk[0].2 + 1,
+            new_count
+        ));
+        for (tag, _, _, line) in hunk {
+            diff.push_str(&format!("{}{}\n", tag, line));
+        }
+        k = end;
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_two_spaces() {
+        let content = "{\n  a = {\n    b = 1;\n  };\n}\n";
+        assert_eq!(detect_indent(content), IndentStyle::Spaces(2));
+    }
+
+    #[test]
+    fn detect_four_spaces() {
+        let content = "{\n    a = {\n        b = 1;\n    };\n    c = 2;\n}\n";
+        assert_eq!(detect_indent(content), IndentStyle::Spaces(4));
+    }
+
+    #[test]
+    fn detect_tabs() {
+        let content = "{\n\ta = {\n\t\tb = 1;\n\t};\n}\n";
+        assert_eq!(detect_indent(content), IndentStyle::Tabs);
+    }
+
+    #[test]
+    fn detect_mixed_uses_most_common_unit() {
+        let content = "{\n\ta = 1;\n    b = {\n        c = 1;\n    };\n    d = 2;\n}\n";
+        assert_eq!(detect_indent(content), IndentStyle::Spaces(4));
+
+        let content = "{\n\ta = 1;\n\tb = 2;\n    c = 3;\n}\n";
+        assert_eq!(detect_indent(content), IndentStyle::Tabs);
+    }
+
+    #[test]
+    fn detect_without_indentation_falls_back_to_default() {
+        assert_eq!(detect_indent("{}\n"), IndentStyle::default());
+        assert_eq!(detect_indent(""), IndentStyle::default());
+    }
+
+    #[test]
+    fn format_file_reindents_and_splits_entries() {
+        let content = "{ config, pkgs, ... }:\n{\n      imports = [\n ./hardware.nix\n   ];\n\n networking = { hostName = \"nixos\";\n        firewall.enable = false; };\n\tservices.xserver = {\n  enable = true;\n      };\n}\n";
+        let expected = "{ config, pkgs, ... }:\n{\n  imports = [\n    ./hardware.nix\n  ];\n\n  networking = {\n    hostName = \"nixos\";\n    firewall.enable = false;\n  };\n  services.xserver = {\n    enable = true;\n  };\n}\n";
+        let formatted = format_file(content, IndentStyle::Spaces(2)).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(
+            format_file(&formatted, IndentStyle::Spaces(2)).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn format_file_uses_indent_style() {
+        let content = "{\n  a = {\n    b = 1;\n  };\n}\n";
+        assert_eq!(
+            format_file(content, IndentStyle::Tabs).unwrap(),
+            "{\n\ta = {\n\t\tb = 1;\n\t};\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_file_keeps_single_line_sets_and_let_in() {
+        let content = "let\nx = { a = 1; b = 2; };\nin\n{\ny = x;\n}\n";
+        assert_eq!(
+            format_file(content, IndentStyle::Spaces(2)).unwrap(),
+            "let\n  x = { a = 1; b = 2; };\nin\n{\n  y = x;\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_file_keeps_multiline_strings() {
+        let content = "{\n    script = ''\n  echo ${pkgs.hello}\n      done\n'';\n}\n";
+        assert_eq!(
+            format_file(content, IndentStyle::Spaces(2)).unwrap(),
+            "{\n  script = ''\n  echo ${pkgs.hello}\n      done\n'';\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_file_rejects_syntax_errors() {
+        assert!(matches!(
+            format_file("{ a = ; ", IndentStyle::default()),
+            Err(mx::ErrorKind::InvalidFile)
+        ));
+    }
+
+    #[test]
+    fn escape_indented_string() {
+        assert_eq!(escape_indented_string_nix("plain"), "plain");
+        assert_eq!(escape_indented_string_nix("a '' b"), "a ''' b");
+        assert_eq!(escape_indented_string_nix("${HOME}"), "''${HOME}");
+    }
+
+    #[test]
+    fn valid_path_values() {
+        assert!(is_valid_path_value("/dev/sda1"));
+        assert!(is_valid_path_value("./hardware-configuration.nix"));
+        assert!(is_valid_path_value("~/data"));
+        assert!(is_valid_path_value("<nixpkgs>"));
+        assert!(is_valid_path_value("\"/dev/disk/by-label/my disk\""));
+    }
+
+    #[test]
+    fn invalid_path_values() {
+        assert!(!is_valid_path_value("/dev/ with spaces"));
+        assert!(!is_valid_path_value("sda1"));
+        assert!(!is_valid_path_value("\"sda1\""));
+        assert!(!is_valid_path_value("\"/dev/${name}\""));
+        assert!(!is_valid_path_value(""));
+    }
+
+    #[test]
+    fn line_diff_identical_is_empty() {
+        assert_eq!(line_diff("a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn line_diff_replaced_line() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        assert_eq!(
+            line_diff(old, new),
+            "@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn line_diff_inserted_line() {
+        assert_eq!(
+            line_diff("a\nc\n", "a\nb\nc\n"),
+            "@@ -1,2 +1,3 @@\n a\n+b\n c\n"
+        );
+    }
+
+    #[test]
+    fn line_diff_local_edit_in_large_file() {
+        let old: String = (1..=50_000).map(|i| format!("{}\n", i)).collect();
+        let new = old.replace("\n25000\n", "\n25000\nadded\n");
+        assert_eq!(
+            line_diff(&old, &new),
+            "@@ -24998,6 +24998,7 @@\n 24998\n 24999\n 25000\n+added\n 25001\n 25002\n 25003\n"
+        );
+    }
+
+    #[test]
+    fn line_diff_distant_changes_make_two_hunks() {
+        let old: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+        let new: String = (1..=20)
+            .map(|i| match i {
+                2 => "two\n".to_string(),
+                19 => "nineteen\n".to_string(),
+                i => format!("{}\n", i),
+            })
+            .collect();
+        let diff = line_diff(&old, &new);
+        assert_eq!(diff.matches("@@ -").count(), 2);
+        assert!(diff.starts_with("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n"));
+        assert!(diff.ends_with("@@ -16,5 +16,5 @@\n 16\n 17\n 18\n-19\n+nineteen\n 20\n"));
+    }
+}
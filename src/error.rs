@@ -4,13 +4,18 @@ use std::{fmt, io, result, string};
 pub enum ErrorKind {
     InvalidFile,
     FileNotFound,
+    FileChangedExternally,
     OptionNotFound,
     FailToLock,
+    BuildInProgress,
     PermissionDenied,
     TransactionNotBegin,
     TransactionAlreadyBegin,
     GitNotCommitted,
+    StashConflict(git2::Oid),
     OptionIsNotList,
+    OptionIsNotAttrSet,
+    OptionTypeMismatch(String),
     InvalidUuid,
     PackageDoesNotHaveAPlugin,
     CPUInfoNofFound,
@@ -20,7 +25,8 @@ pub enum ErrorKind {
     DesktopFileNotFound,
     InvalidNixString,
     GetVGAInfoError(&'static str),
-    BuildError(String),
+    BuildFailed { stdout: String, stderr: String },
+    RebuildToolMissing(String),
     RequestSenderError(String),
     NixCommandError(String),
     InvalidArgument(String),
@@ -43,12 +49,17 @@ impl fmt::Display for ErrorKind {
                 Self::InvalidFile => "File is not a valid Nix file",
                 Self::OptionNotFound => "Option not found",
                 Self::FileNotFound => "File not found",
+                Self::FileChangedExternally => "File was modified by another process",
                 Self::TransactionNotBegin => "Transaction don't start",
                 Self::TransactionAlreadyBegin => "Transaction already start",
                 Self::FailToLock => "Impossible to take lock",
+                Self::BuildInProgress => "Another transaction is already waiting to build",
                 Self::PermissionDenied => "Permission denied",
                 Self::GitNotCommitted => "In repository file are untracked or not committed",
+                Self::StashConflict(_) =>
+                    "Stashed changes could not be re-applied, they are kept in the git stash",
                 Self::OptionIsNotList => "This option is not a list",
+                Self::OptionIsNotAttrSet => "This option is not an attribute set",
                 Self::InvalidUuid => "Invalid uuid for device",
                 Self::PackageDoesNotHaveAPlugin => "This package does not have a plugin",
                 Self::CPUInfoNofFound => "CPU info not found",
@@ -58,6 +69,7 @@ impl fmt::Display for ErrorKind {
                 Self::DesktopFileNotFound => "Desktop icon not found",
                 Self::InvalidNixString => "Impossible to parse nix string in configuration",
                 Self::InvalidArgument(s) => s.as_str(),
+                Self::OptionTypeMismatch(s) => s.as_str(),
                 Self::RequestSenderError(s) => s.as_str(),
                 Self::GetVGAInfoError(e) => e,
                 Self::IOError(e) => {
@@ -68,7 +80,11 @@ impl fmt::Display for ErrorKind {
                     s = e.to_string();
                     s.as_str()
                 }
-                Self::BuildError(s) => s,
+                Self::BuildFailed { stderr, .. } => stderr,
+                Self::RebuildToolMissing(program) => {
+                    s = format!("Rebuild program {} not found", program);
+                    s.as_str()
+                }
                 Self::NixCommandError(s) => s.as_str(),
                 Self::FromUtf8Error(e) => {
                     s = e.to_string();
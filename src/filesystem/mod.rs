@@ -0,0 +1,445 @@
+use rnix::ast::{self, HasEntry};
+use std::path::Path;
+use std::{fs, io, process};
+
+use crate::{
+    core::{
+        list::List as mxList,
+        option::Option as mxOption,
+        parsed::ParsedNix,
+        transaction::{self, file_lock::NixFile, transaction::BuildCommand},
+        utils::{escape_indented_string_nix, escape_string_nix, string_nix_to_value},
+    },
+    mx,
+};
+
+const FILE_SYSTEM_PATH: &str = "fstab.nix";
+const SWAP_DEVICES: &str = "swapDevices";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSystemEntry {
+    pub mount_point: String,
+    pub device: String,
+    pub fs_type: String,
+    pub options: Vec<String>,
+}
+
+pub fn add_entry_no_transaction(
+    fstab: &mut NixFile,
+    mount_point: &str,
+    device: &str,
+    fs_type: &str,
+    option: &[&str],
+    encrypted: bool,
+) -> mx::Result<()> {
+    add_entry_in_str(
+        fstab.get_mut_file_content()?,
+        mount_point,
+        device,
+        fs_type,
+        option,
+        encrypted,
+    )
+}
+
+/// Same as [`add_entry_no_transaction`] on an in-memory content, which is
+/// left untouched when an error is returned.
+pub fn add_entry_in_str(
+    content: &mut String,
+    mount_point: &str,
+    device: &str,
+    fs_type: &str,
+    option: &[&str],
+    encrypted: bool,
+) -> mx::Result<()> {
+    let mut fstab = content.clone();
+    let root_option = format!("fileSystems.\"{}\"", mount_point);
+    if encrypted {
+        let uuid = device
+            .strip_prefix("/dev/disk/by-uuid/")
+            .ok_or(mx::ErrorKind::InvalidUuid)?;
+        let luks_name = format!("luks-{}", uuid);
+        let luks_path = format!("/dev/mapper/{}", luks_name);
+        let luks_option = format!("boot.initrd.luks.devices.\"{}\"", luks_name);
+        mxOption::new(&format!("{}.device", luks_option)).set_string_in_str(&mut fstab, device)?;
+
+        mxOption::new(format!("{}.device", root_option).as_str())
+            .set_string_in_str(&mut fstab, &luks_path)?;
+    } else {
+        mxOption::new(format!("{}.device", root_option).as_str())
+            .set_string_in_str(&mut fstab, device)?;
+    }
+
+    mxOption::new(format!("{}.fsType", root_option).as_str())
+        .set_string_in_str(&mut fstab, fs_type)?;
+
+    let option_path = format!("{}.options", root_option);
+
+    mxOption::new(&option_path).set_option_to_default_in_str(&mut fstab)?;
+
+    let list_opt = mxList::new(&option_path, true);
+    for o in option {
+        list_opt.add_in_str(&mut fstab, &escape_string_nix(o))?;
+    }
+    *content = fstab;
+    Ok(())
+}
+
+pub fn add_entry(
+    config_dir: &str,
+    mount_point: &str,
+    device: &str,
+    fs_type: &str,
+    option: &[&str],
+    encrypted: bool,
+) -> mx::Result<()> {
+    transaction::make_transaction(
+        &format!("Add {} entry with device: {} in fstab", mount_point, device),
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| add_entry_no_transaction(file, mount_point, device, fs_type, option, encrypted),
+    )
+}
+
+/// Adds a bind mount of `source_dir` on `mount_point`. The `bind` option is
+/// always written, before `extra_options`.
+pub fn add_bind_in_str(
+    content: &mut String,
+    mount_point: &str,
+    source_dir: &str,
+    extra_options: &[&str],
+) -> mx::Result<()> {
+    let options: Vec<&str> = std::iter::once("bind")
+        .chain(extra_options.iter().copied().filter(|o| *o != "bind"))
+        .collect();
+    add_entry_in_str(content, mount_point, source_dir, "none", &options, false)
+}
+
+#[allow(dead_code)]
+pub fn add_bind(
+    config_dir: &str,
+    mount_point: &str,
+    source_dir: &str,
+    extra_options: &[&str],
+) -> mx::Result<()> {
+    transaction::make_transaction(
+        &format!(
+            "Add bind mount of {} on {} in fstab",
+            source_dir, mount_point
+        ),
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| {
+            add_bind_in_str(
+                file.get_mut_file_content()?,
+                mount_point,
+                source_dir,
+                extra_options,
+            )
+        },
+    )
+}
+
+/// Adds a tmpfs of `size` (like `2G` or `50%`) on `mount_point`. The
+/// `size=` option is written first, replacing any size in `extra_options`.
+pub fn add_tmpfs_in_str(
+    content: &mut String,
+    mount_point: &str,
+    size: &str,
+    extra_options: &[&str],
+) -> mx::Result<()> {
+    let size_option = format!("size={}", size);
+    let options: Vec<&str> = std::iter::once(size_option.as_str())
+        .chain(
+            extra_options
+                .iter()
+                .copied()
+                .filter(|o| !o.starts_with("size=")),
+        )
+        .collect();
+    add_entry_in_str(content, mount_point, "tmpfs", "tmpfs", &options, false)
+}
+
+#[allow(dead_code)]
+pub fn add_tmpfs(
+    config_dir: &str,
+    mount_point: &str,
+    size: &str,
+    extra_options: &[&str],
+) -> mx::Result<()> {
+    transaction::make_transaction(
+        &format!("Add tmpfs of size {} on {} in fstab", size, mount_point),
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| {
+            add_tmpfs_in_str(
+                file.get_mut_file_content()?,
+                mount_point,
+                size,
+                extra_options,
+            )
+        },
+    )
+}
+
+/// Adds `device` to `swapDevices`, replacing a swap already declared on the
+/// same device. `options` are written in its `options` list when not empty.
+pub fn add_swap_entry_in_str(
+    content: &mut String,
+    device: &str,
+    options: &[&str],
+) -> mx::Result<()> {
+    let mut fstab = content.clone();
+    remove_swap_entry_in_str(&mut fstab, device)?;
+    let mut entry = format!("{{ device = {};", escape_string_nix(device));
+    if !options.is_empty() {
+        let options: Vec<String> = options.iter().map(|o| escape_string_nix(o)).collect();
+        entry.push_str(&format!(" options = [ {} ];", options.join(" ")));
+    }
+    entry.push_str(" }");
+    mxList::new(SWAP_DEVICES, false).add_in_str(&mut fstab, &entry)?;
+    *content = fstab;
+    Ok(())
+}
+
+/// Removes the element of `swapDevices` whose `device` field is `device`.
+/// Returns `false` if no swap is declared on it.
+pub fn remove_swap_entry_in_str(content: &mut String, device: &str) -> mx::Result<bool> {
+    mxList::new(SWAP_DEVICES, false).remove_first_matching_in_str(content, |entry| {
+        mxOption::new("device")
+            .get_string_in_str(entry)
+            .is_ok_and(|d| d == device)
+    })
+}
+
+#[allow(dead_code)]
+pub fn add_swap_entry(config_dir: &str, device: &str, options: &[&str]) -> mx::Result<()> {
+    transaction::make_transaction(
+        &format!("Add swap device {} in fstab", device),
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| add_swap_entry_in_str(file.get_mut_file_content()?, device, options),
+    )
+}
+
+fn remove_if_empty_set(content: &mut String, option: &str) -> mx::Result<()> {
+    let opt = mxOption::new(option);
+    let is_empty = match opt.get_in_str(content) {
+        Ok(value) => value
+            .strip_prefix('{')
+            .and_then(|v| v.strip_suffix('}'))
+            .is_some_and(|inner| inner.trim().is_empty()),
+        Err(mx::ErrorKind::OptionNotFound) => false,
+        Err(e) => return Err(e),
+    };
+    if is_empty {
+        opt.set_option_to_default_in_str(content)?;
+    }
+    Ok(())
+}
+
+pub fn remove_entry_no_transaction(fstab: &mut NixFile, mount_point: &str) -> mx::Result<bool> {
+    let root_option = format!("fileSystems.\"{}\"", mount_point);
+    let found = mxOption::new(&root_option).set_option_all_instance_to_default(fstab)?;
+    if found {
+        remove_if_empty_set(fstab.get_mut_file_content()?, "fileSystems")?;
+    }
+    Ok(found)
+}
+
+pub fn remove_entry(config_dir: &str, mount_point: &str) -> mx::Result<bool> {
+    transaction::make_transaction(
+        &format!("remove {} entry in fstab", mount_point),
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| remove_entry_no_transaction(file, mount_point),
+    )
+}
+
+/// Declares the file `/etc/<etc_path>` holding `text` through
+/// `environment.etc."<etc_path>".text`, with its `mode` if given. A file
+/// already declared on the same path is replaced.
+pub fn add_etc_file_in_str(
+    content: &mut String,
+    etc_path: &str,
+    text: &str,
+    mode: Option<&str>,
+) -> mx::Result<()> {
+    let mut edited = content.clone();
+    let root_option = format!("environment.etc.{}", escape_string_nix(etc_path));
+    let lines: Vec<String> = text.lines().map(escape_indented_string_nix).collect();
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    mxOption::new(&format!("{}.text", root_option)).set_multiline_in_str(&mut edited, &lines)?;
+
+    let mode_option = format!("{}.mode", root_option);
+    match mode {
+        Some(mode) => {
+            mxOption::new(&mode_option).set_string_in_str(&mut edited, mode)?;
+        }
+        None => {
+            mxOption::new(&mode_option).set_option_to_default_in_str(&mut edited)?;
+        }
+    }
+    *content = edited;
+    Ok(())
+}
+
+/// Removes every declaration of `/etc/<etc_path>`, flattened ones like
+/// `environment.etc."<etc_path>".text` included, then `environment.etc` and
+/// `environment` if they are left empty. Returns `false` if the file was not
+/// declared.
+pub fn remove_etc_file_in_str(content: &mut String, etc_path: &str) -> mx::Result<bool> {
+    let root_option = format!("environment.etc.{}", escape_string_nix(etc_path));
+    let found = mxOption::new(&root_option).set_option_tree_to_default_in_str(content)?;
+    if found {
+        remove_if_empty_set(content, "environment.etc")?;
+        remove_if_empty_set(content, "environment")?;
+    }
+    Ok(found)
+}
+
+fn unquote(value: &str) -> String {
+    string_nix_to_value(value).unwrap_or(value).to_string()
+}
+
+fn get_or_empty(fstab: &ParsedNix, option: &str) -> mx::Result<String> {
+    match fstab.get_option(option) {
+        Ok(value) => Ok(unquote(value)),
+        Err(mx::ErrorKind::OptionNotFound) => Ok(String::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lists the file systems declared in `content`, without editing anything.
+pub fn list_entries_in_str(content: &str) -> mx::Result<Vec<FileSystemEntry>> {
+    let fstab = ParsedNix::new(content);
+    let file_systems = match fstab.get_option("fileSystems") {
+        Ok(value) => value.to_string(),
+        Err(mx::ErrorKind::OptionNotFound) => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let Some(ast::Expr::AttrSet(set)) = rnix::Root::parse(&file_systems).tree().expr() else {
+        return Ok(vec![]);
+    };
+
+    let mut keys: Vec<String> = vec![];
+    for entry in set.entries() {
+        let ast::Entry::AttrpathValue(apv) = entry else {
+            continue;
+        };
+        if let Some(key) = apv.attrpath().and_then(|p| p.attrs().next()) {
+            let key = key.to_string();
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+
+    let mut entries = vec![];
+    for key in keys {
+        let root_option = format!("fileSystems.{}", key);
+        let options = match fstab.list_elements(&format!("{}.options", root_option)) {
+            Ok(elements) => elements.into_iter().map(unquote).collect(),
+            Err(mx::ErrorKind::OptionNotFound) => vec![],
+            Err(e) => return Err(e),
+        };
+        entries.push(FileSystemEntry {
+            mount_point: unquote(&key),
+            device: get_or_empty(&fstab, &format!("{}.device", root_option))?,
+            fs_type: get_or_empty(&fstab, &format!("{}.fsType", root_option))?,
+            options,
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads the file systems of `fstab.nix` in `config_dir`, none if the file
+/// does not exist.
+pub fn list_entries(config_dir: &str) -> mx::Result<Vec<FileSystemEntry>> {
+    match fs::read_to_string(Path::new(config_dir).join(FILE_SYSTEM_PATH)) {
+        Ok(content) => list_entries_in_str(&content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(mx::ErrorKind::IOError(e)),
+    }
+}
+
+pub fn add_swap_no_transaction(fstab: &mut NixFile, device: &str) -> mx::Result<()> {
+    add_swap_entry_in_str(fstab.get_mut_file_content()?, device, &[])
+}
+
+pub fn add_swap(config_dir: &str, device: &str) -> mx::Result<()> {
+    transaction::make_transaction(
+        &format!("Add swap device: {}", device),
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| add_swap_no_transaction(file, device),
+    )
+}
+
+pub fn remove_swap_no_transaction(fstab: &mut NixFile, device: &str) -> mx::Result<bool> {
+    remove_swap_entry_in_str(fstab.get_mut_file_content()?, device)
+}
+
+pub fn remove_swap(config_dir: &str, device: &str) -> mx::Result<bool> {
+    transaction::make_transaction(
+        &format!("Remove swap device: {}", device),
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| remove_swap_no_transaction(file, device),
+    )
+}
+
+pub(super) fn get_filesystem_from_fstab(root_dir: &str) -> mx::Result<String> {
+    let mut cmd_full = process::Command::new("nixos-generate-config");
+    cmd_full.args(["--show-hardware-config"]);
+    if root_dir != "/" {
+        cmd_full.args(["--root", root_dir]);
+    }
+    let full = cmd_full.output().map_err(mx::ErrorKind::IOError)?;
+
+    let mut cmd_no_fs = process::Command::new("nixos-generate-config");
+    cmd_no_fs.args(["--show-hardware-config", "--no-filesystems"]);
+    if root_dir != "/" {
+        cmd_no_fs.args(["--root", root_dir]);
+    }
+    let no_fs = cmd_no_fs.output().map_err(mx::ErrorKind::IOError)?;
+
+    let full_str = String::from_utf8(full.stdout).map_err(|_| mx::ErrorKind::InvalidFile)?;
+    let no_fs_str = String::from_utf8(no_fs.stdout).map_err(|_| mx::ErrorKind::InvalidFile)?;
+
+    let no_fs_lines: std::collections::HashSet<&str> = no_fs_str.lines().collect();
+    let diff: Vec<&str> = full_str
+        .lines()
+        .filter(|line| !no_fs_lines.contains(line))
+        .collect();
+
+    Ok(diff.join("\n"))
+}
+
+pub fn def_filesystem_from_unix_fstab_no_transaction(fstab: &mut NixFile) -> mx::Result<()> {
+    let content: &mut String = fstab.get_mut_file_content()?;
+    let new_file: String = get_filesystem_from_fstab("/")?;
+    *content = format!("{{config, lib, pkgs, ...}}:\n{{\n{}\n}}\n", new_file);
+    Ok(())
+}
+
+pub fn def_filesystem_from_unix_fstab(config_dir: &str) -> mx::Result<()> {
+    transaction::make_transaction(
+        "Reset filesystem",
+        config_dir,
+        FILE_SYSTEM_PATH,
+        BuildCommand::Switch,
+        |file| def_filesystem_from_unix_fstab_no_transaction(file),
+    )
+}
+
+#[cfg(test)]
+#[path = "tests.rs"]
+mod tests;
@@ -0,0 +1,323 @@
+use super::{
+    FILE_SYSTEM_PATH, FileSystemEntry, add_bind_in_str, add_entry_in_str, add_entry_no_transaction,
+    add_etc_file_in_str, add_swap_entry_in_str, add_swap_no_transaction, add_tmpfs_in_str,
+    list_entries, list_entries_in_str, remove_entry_no_transaction, remove_etc_file_in_str,
+    remove_swap_entry_in_str, remove_swap_no_transaction,
+};
+use crate::core::transaction::{Transaction, file_lock::NixFile, transaction::BuildCommand};
+use crate::mx;
+use std::fs;
+use tempfile::TempDir;
+
+const EMPTY_FSTAB: &str = "{config, lib, pkgs, ...}:\n{\n}\n";
+
+fn commit_all(repo: &git2::Repository) {
+    let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+}
+
+/// Runs `f` on `fstab.nix` holding `content` and returns its result with the
+/// resulting content; the transaction is rolled back afterwards.
+fn edit_fstab<F, R>(content: &str, f: F) -> mx::Result<(R, String)>
+where
+    F: FnOnce(&mut NixFile) -> mx::Result<R>,
+{
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let repo = git2::Repository::init(dir.path()).expect("failed to init git repo");
+    fs::write(
+        dir.path().join("configuration.nix"),
+        "{config, lib, pkgs, ...}:\n{\n  imports = [\n    ./fstab.nix\n  ];\n}\n",
+    )
+    .expect("failed to write configuration.nix");
+    fs::write(dir.path().join(FILE_SYSTEM_PATH), content).expect("failed to write fstab.nix");
+    commit_all(&repo);
+
+    let mut transaction = Transaction::new(
+        &format!("{}/", dir.path().to_str().unwrap()),
+        "test",
+        BuildCommand::Switch,
+    )?;
+    transaction.add_file(FILE_SYSTEM_PATH)?;
+    transaction.begin()?;
+    let file = transaction.get_file(FILE_SYSTEM_PATH)?;
+    let result = f(file).and_then(|ret| Ok((ret, file.get_file_content()?.clone())));
+    transaction.rollback()?;
+    result
+}
+
+#[test]
+fn remove_entry_after_add_restores_file() {
+    let (removed, content) = edit_fstab(EMPTY_FSTAB, |file| {
+        add_entry_no_transaction(file, "/mnt/data", "/dev/sdb1", "ext4", &["noatime"], false)?;
+        remove_entry_no_transaction(file, "/mnt/data")
+    })
+    .unwrap();
+
+    assert!(removed);
+    assert_eq!(content, EMPTY_FSTAB);
+}
+
+#[test]
+fn remove_entry_keeps_other_mount_points() {
+    let (removed, content) = edit_fstab(EMPTY_FSTAB, |file| {
+        add_entry_no_transaction(file, "/mnt/data", "/dev/sdb1", "ext4", &[], false)?;
+        add_entry_no_transaction(file, "/mnt/backup", "/dev/sdc1", "btrfs", &[], false)?;
+        remove_entry_no_transaction(file, "/mnt/data")
+    })
+    .unwrap();
+
+    assert!(removed);
+    assert!(!content.contains("/mnt/data"));
+    assert!(content.contains("fileSystems"));
+    assert!(content.contains("device = \"/dev/sdc1\";"));
+}
+
+#[test]
+fn remove_absent_entry_returns_false() {
+    let (removed, content) = edit_fstab(EMPTY_FSTAB, |file| {
+        remove_entry_no_transaction(file, "/mnt/data")
+    })
+    .unwrap();
+
+    assert!(!removed);
+    assert_eq!(content, EMPTY_FSTAB);
+}
+
+#[test]
+fn add_entry_writes_device_fs_type_and_options() {
+    let (_, content) = edit_fstab(EMPTY_FSTAB, |file| {
+        add_entry_no_transaction(
+            file,
+            "/mnt/data",
+            "/dev/sdb1",
+            "ext4",
+            &["noatime", "nofail"],
+            false,
+        )
+    })
+    .unwrap();
+
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  fileSystems = {\n    \"/mnt/data\" = {\n      device = \"/dev/sdb1\";\n      fsType = \"ext4\";\n      options = [\n        \"noatime\"\n        \"nofail\"\n      ];\n    };\n  };\n}\n"
+    );
+}
+
+#[test]
+fn list_entries_with_two_mounts() {
+    let content = "{config, lib, pkgs, ...}:\n{\n  fileSystems = {\n    \"/\" = {\n      device = \"/dev/sda1\";\n      fsType = \"ext4\";\n    };\n    \"/mnt/data\" = {\n      device = \"/dev/sdb1\";\n      fsType = \"btrfs\";\n      options = [\n        \"noatime\"\n        \"compress=zstd\"\n      ];\n    };\n  };\n}\n";
+    let entries = list_entries_in_str(content).unwrap();
+
+    assert_eq!(
+        entries,
+        vec![
+            FileSystemEntry {
+                mount_point: "/".to_string(),
+                device: "/dev/sda1".to_string(),
+                fs_type: "ext4".to_string(),
+                options: vec![],
+            },
+            FileSystemEntry {
+                mount_point: "/mnt/data".to_string(),
+                device: "/dev/sdb1".to_string(),
+                fs_type: "btrfs".to_string(),
+                options: vec!["noatime".to_string(), "compress=zstd".to_string()],
+            },
+        ]
+    );
+}
+
+#[test]
+fn list_entries_without_file_systems_is_empty() {
+    let entries = list_entries_in_str(EMPTY_FSTAB).unwrap();
+    assert!(entries.is_empty());
+}
+
+#[test]
+fn list_entries_reads_fstab_without_transaction() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let config_dir = dir.path().to_str().unwrap();
+    assert!(list_entries(config_dir).unwrap().is_empty());
+    assert!(!dir.path().join(FILE_SYSTEM_PATH).exists());
+
+    fs::write(
+        dir.path().join(FILE_SYSTEM_PATH),
+        "{\n  fileSystems = {\n    \"/\" = {\n      device = \"/dev/sda1\";\n    };\n  };\n}\n",
+    )
+    .unwrap();
+    let entries = list_entries(config_dir).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].device, "/dev/sda1");
+}
+
+#[test]
+fn add_entry_escapes_quotes_and_interpolation() {
+    let device = "/dev/disk/by-label/my \"disk\" ${x}";
+    let (_, content) = edit_fstab(EMPTY_FSTAB, |file| {
+        add_entry_no_transaction(file, "/mnt/data", device, "ext4", &[], false)
+    })
+    .unwrap();
+
+    assert!(rnix::Root::parse(&content).errors().is_empty());
+    assert!(content.contains(r#"device = "/dev/disk/by-label/my \"disk\" \${x}";"#));
+}
+
+#[test]
+fn add_entry_in_str_writes_entry() {
+    let mut content = EMPTY_FSTAB.to_string();
+    add_entry_in_str(
+        &mut content,
+        "/mnt/data",
+        "/dev/sdb1",
+        "ext4",
+        &["noatime"],
+        false,
+    )
+    .unwrap();
+
+    assert!(content.contains(r#"device = "/dev/sdb1";"#));
+    assert!(content.contains(r#"fsType = "ext4";"#));
+    assert!(content.contains(r#""noatime""#));
+}
+
+#[test]
+fn add_entry_in_str_errors_leave_content_untouched() {
+    let mut content = EMPTY_FSTAB.to_string();
+    assert!(matches!(
+        add_entry_in_str(&mut content, "/mnt/data", "/dev/sdb1", "ext4", &[], true),
+        Err(mx::ErrorKind::InvalidUuid)
+    ));
+    assert_eq!(content, EMPTY_FSTAB);
+
+    let broken = "{config, lib, pkgs, ...}:\n{\n  fileSystems = ;\n}\n";
+    let mut content = broken.to_string();
+    assert!(matches!(
+        add_entry_in_str(&mut content, "/mnt/data", "/dev/sdb1", "ext4", &[], false),
+        Err(mx::ErrorKind::InvalidFile)
+    ));
+    assert_eq!(content, broken);
+}
+
+#[test]
+fn add_bind_writes_bind_option() {
+    let mut content = EMPTY_FSTAB.to_string();
+    add_bind_in_str(
+        &mut content,
+        "/srv/data",
+        "/home/user/data",
+        &["ro", "bind"],
+    )
+    .unwrap();
+
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  fileSystems = {\n    \"/srv/data\" = {\n      device = \"/home/user/data\";\n      fsType = \"none\";\n      options = [\n        \"bind\"\n        \"ro\"\n      ];\n    };\n  };\n}\n"
+    );
+}
+
+#[test]
+fn add_tmpfs_writes_size_option() {
+    let mut content = EMPTY_FSTAB.to_string();
+    add_tmpfs_in_str(&mut content, "/tmp", "2G", &["mode=1777", "size=1G"]).unwrap();
+
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  fileSystems = {\n    \"/tmp\" = {\n      device = \"tmpfs\";\n      fsType = \"tmpfs\";\n      options = [\n        \"size=2G\"\n        \"mode=1777\"\n      ];\n    };\n  };\n}\n"
+    );
+}
+
+#[test]
+fn swap_entries_added_and_removed_by_device() {
+    let mut content = EMPTY_FSTAB.to_string();
+    add_swap_entry_in_str(&mut content, "/dev/sda2", &[]).unwrap();
+    add_swap_entry_in_str(&mut content, "/dev/sdb2", &["nofail"]).unwrap();
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  swapDevices = [\n    { device = \"/dev/sda2\"; }\n    { device = \"/dev/sdb2\"; options = [ \"nofail\" ]; }\n  ];\n}\n"
+    );
+
+    assert!(remove_swap_entry_in_str(&mut content, "/dev/sda2").unwrap());
+    assert!(!remove_swap_entry_in_str(&mut content, "/dev/sda2").unwrap());
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  swapDevices = [\n    { device = \"/dev/sdb2\"; options = [ \"nofail\" ]; }\n  ];\n}\n"
+    );
+}
+
+#[test]
+fn swap_added_in_file_is_removed_by_device() {
+    let (removed, content) = edit_fstab(EMPTY_FSTAB, |file| {
+        add_swap_no_transaction(file, "/dev/sda2")?;
+        assert_eq!(
+            file.get_file_content()?,
+            "{config, lib, pkgs, ...}:\n{\n  swapDevices = [\n    { device = \"/dev/sda2\"; }\n  ];\n}\n"
+        );
+        let mut content = file.get_file_content()?.clone();
+        add_swap_entry_in_str(&mut content, "/dev/sdb2", &[])?;
+        *file.get_mut_file_content()? = content;
+        Ok(remove_swap_no_transaction(file, "/dev/sdb2")?
+            && remove_swap_no_transaction(file, "/dev/sda2")?)
+    })
+    .unwrap();
+    assert!(removed);
+    assert_eq!(content, EMPTY_FSTAB);
+}
+
+#[test]
+fn swap_entry_on_same_device_is_replaced() {
+    let mut content = EMPTY_FSTAB.to_string();
+    add_swap_entry_in_str(&mut content, "/dev/sda2", &[]).unwrap();
+    add_swap_entry_in_str(&mut content, "/dev/sda2", &["nofail"]).unwrap();
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  swapDevices = [\n    { device = \"/dev/sda2\"; options = [ \"nofail\" ]; }\n  ];\n}\n"
+    );
+}
+
+#[test]
+fn etc_file_added_then_removed() {
+    let mut content = EMPTY_FSTAB.to_string();
+    add_etc_file_in_str(
+        &mut content,
+        "nix/nix.conf",
+        "experimental-features = nix-command\nextra-paths = ${HOME}",
+        Some("0644"),
+    )
+    .unwrap();
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  environment = {\n    etc = {\n      \"nix/nix.conf\" = {\n        text = ''\n          experimental-features = nix-command\n          extra-paths = ''${HOME}\n        '';\n        mode = \"0644\";\n      };\n    };\n  };\n}\n"
+    );
+    assert!(rnix::Root::parse(&content).errors().is_empty());
+
+    assert!(remove_etc_file_in_str(&mut content, "nix/nix.conf").unwrap());
+    assert_eq!(content, EMPTY_FSTAB);
+    assert!(!remove_etc_file_in_str(&mut content, "nix/nix.conf").unwrap());
+}
+
+#[test]
+fn etc_file_removed_in_flattened_form() {
+    let mut content = String::from(
+        "{\n  environment.etc.\"motd\".text = \"hello\";\n  environment.etc.\"motd\".mode = \"0600\";\n  networking.hostName = \"host\";\n}\n",
+    );
+    assert!(remove_etc_file_in_str(&mut content, "motd").unwrap());
+    assert_eq!(content, "{\n  networking.hostName = \"host\";\n}\n");
+}
+
+#[test]
+fn etc_file_replaced_without_mode() {
+    let mut content = EMPTY_FSTAB.to_string();
+    add_etc_file_in_str(&mut content, "motd", "hello", Some("0600")).unwrap();
+    add_etc_file_in_str(&mut content, "motd", "bye", None).unwrap();
+    assert_eq!(
+        content,
+        "{config, lib, pkgs, ...}:\n{\n  environment = {\n    etc = {\n      \"motd\" = {\n        text = ''\n          bye\n        '';\n      };\n    };\n  };\n}\n"
+    );
+}
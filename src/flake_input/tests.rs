@@ -2,7 +2,10 @@ use super::{
     FlakeInput, remove_follower_no_transaction, remove_input_no_transaction,
     set_follower_no_transaction,
 };
-use crate::core::transaction::{self, transaction::BuildCommand};
+use crate::core::transaction::{
+    self, Transaction,
+    transaction::{BuildCommand, LockPaths},
+};
 use git2::Repository;
 use std::fs;
 use tempfile::tempdir;
@@ -16,26 +19,21 @@ fn create_flake_file(content: &str) -> (tempfile::TempDir, String) {
     (dir, path)
 }
 
-fn lock_build_queue() -> fs::File {
-    let f = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open("/tmp/mx-queue-build.lock")
-        .expect("failed to create build-queue lock file");
-    f.lock().expect("failed to lock build-queue lock file");
-    f
+/// Transaction on the repo at `path` whose rebuild is a no-op, with build
+/// locks of its own.
+fn noop_transaction(path: &str, description: &str) -> Transaction<'static> {
+    Transaction::new(&format!("{}/", path), description, BuildCommand::Switch)
+        .unwrap()
+        .with_rebuild_program("true")
+        .with_lock_paths(LockPaths::in_dir(&format!("{}/.git", path)))
 }
 
 #[test]
 fn add_follower_creates_follows_option() {
     let (_dir, path) = create_flake_file("{ config, lib, pkgs, ... }:\n{\n}\n");
-    let _guard = lock_build_queue();
-    transaction::make_transaction(
-        "add follower",
-        &format!("{}/", path),
+    transaction::make_transaction_with(
+        noop_transaction(&path, "add follower"),
         "flake.nix",
-        BuildCommand::Switch,
         |file| set_follower_no_transaction(file, "foo", FlakeInput::Nixpkgs),
     )
     .unwrap();
@@ -49,12 +47,9 @@ fn remove_follower_deletes_follows_option() {
     let (_dir, path) = create_flake_file(
         "{ config, lib, pkgs, ... }:\n{\n  inputs.foo = {\n    url = \"github:example/repo\";\n    follows = \"nixpkgs\";\n  };\n}\n",
     );
-    let _guard = lock_build_queue();
-    let removed = transaction::make_transaction(
-        "remove follower",
-        &format!("{}/", path),
+    let removed = transaction::make_transaction_with(
+        noop_transaction(&path, "remove follower"),
         "flake.nix",
-        BuildCommand::Switch,
         |file| remove_follower_no_transaction(file, "foo"),
     )
     .unwrap();
@@ -70,12 +65,9 @@ fn remove_input_deletes_input_block() {
     let (_dir, path) = create_flake_file(
         "{ config, lib, pkgs, ... }:\n{\n  inputs.foo = {\n    url = \"github:example/repo\";\n    follows = \"nixpkgs\";\n  };\n}\n",
     );
-    let _guard = lock_build_queue();
-    let removed = transaction::make_transaction(
-        "remove input",
-        &format!("{}/", path),
+    let removed = transaction::make_transaction_with(
+        noop_transaction(&path, "remove input"),
         "flake.nix",
-        BuildCommand::Switch,
         |file| remove_input_no_transaction(file, "foo"),
     )
     .unwrap();
@@ -83,7 +83,7 @@ pub fn init_repo(root_path: &str) -> mx::Result<()> {
 
     #[cfg(debug_assertions)]
     let mut initial_transaction =
-        Transaction::new(&path_config, "initial commit", BuildCommand::Boot)?;
+        Transaction::new(&path_config, "initial commit", BuildCommand::BuildVm)?;
     #[cfg(not(debug_assertions))]
     let mut initial_transaction =
         Transaction::new(&path_config, "initial commit", BuildCommand::Install)?;
@@ -75,11 +75,7 @@ pub fn set_locale_extra_settings_no_transaction(
         ("console.keyMap", format!("\"{}\"", console_keymap)),
     ];
 
-    for (key, value) in &options {
-        mxOption::new(key).set(file, value)?;
-    }
-
-    Ok(())
+    mxOption::set_many(file, &options)
 }
 
 pub fn set_locale_no_transaction(